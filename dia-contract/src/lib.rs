@@ -1,7 +1,10 @@
 use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
 use near_sdk::serde::{Deserialize, Serialize};
-use near_sdk::json_types::{U128};
-use near_sdk::{env, near_bindgen, AccountId};
+use near_sdk::json_types::{Base64VecU8, U128, U64};
+use near_sdk::collections::{LookupMap, LookupSet, TreeMap, UnorderedMap, UnorderedSet};
+use near_sdk::serde_json::Value;
+use near_sdk::{env, near_bindgen, AccountId, Gas, Promise, PromiseOrValue, PromiseResult};
+use std::ops::Bound;
 
 
 #[global_allocator]
@@ -9,7 +12,95 @@ static ALLOC: near_sdk::wee_alloc::WeeAlloc = near_sdk::wee_alloc::WeeAlloc::INI
 
 const ONE_NEAR:u128 = 1_000_000_000_000_000_000_000_000;
 const ONE_NEAR_CENT:u128 = ONE_NEAR/100;
-const DEPOSIT_FOR_REQUEST: u128 = ONE_NEAR_CENT; // amount that clients have to attach to make a request to the api
+const DEPOSIT_FOR_REQUEST: u128 = ONE_NEAR_CENT; // default amount that clients have to attach to make a request to the api
+const MAX_PAGE_LIMIT: u64 = 100; // max amount of requests returned by a paginated view
+const MAX_SYMBOLS_PER_CALL: usize = 100; // max amount of symbols added or removed in a single call
+const ONE_HOUR_NS: u64 = 3_600_000_000_000;
+const DEFAULT_REQUEST_TTL: u64 = 24*ONE_HOUR_NS; // time (in nanoseconds) a request waits to be served before expiring
+const STORAGE_PRICE_PER_BYTE: u128 = 10_000_000_000_000_000_000; // yocto-near staked for every byte of storage
+const DEFAULT_PURGE_BOUNTY: u128 = ONE_NEAR_CENT/10; // max amount paid for every expired request purged
+const MAX_PURGE_SCAN: usize = 500; // max amount of queued requests inspected by a purge
+const USD_PRICE_DECIMALS: u128 = 1_000_000; // NEAR/USD prices are stored with 6 decimals
+const DEFAULT_PRICE_SLIPPAGE_BPS: u32 = 200; // buffer (in basis points) added to usd fees converted to yocto-near
+const DEFAULT_MAX_PRICE_AGE: u64 = ONE_HOUR_NS; // requests priced in usd are refused when the NEAR/USD price is older
+const GAS_FOR_FT_TRANSFER: Gas = 10_000_000_000_000;
+const GAS_FOR_ON_TOKEN_TRANSFER: Gas = 10_000_000_000_000; // gas of the `on_token_transfer` callback of token refunds and withdrawals
+const GAS_FOR_MIGRATE: Gas = 100_000_000_000_000; // gas of the `migrate` call made by `upgrade`
+const GAS_FOR_DELIVERY: Gas = 30_000_000_000_000; // gas of the client callback called by `fulfill`
+const GAS_FOR_SETTLE_DELIVERY: Gas = 20_000_000_000_000; // gas used by `on_fulfill` itself to settle a delivery
+const GAS_FOR_ON_FULFILL: Gas = GAS_FOR_SETTLE_DELIVERY + GAS_FOR_FT_TRANSFER + GAS_FOR_ON_TOKEN_TRANSFER; // `on_fulfill` may refund a failed token request
+const DELIVERY_GRACE_PERIOD: u64 = ONE_HOUR_NS; // time after the expiration a delivery can stay unsettled before it can be closed as expired
+const ACCOUNT_STORAGE_BYTES: u128 = 125; // storage of a registered account: record overhead, key with a 64 bytes account id and balance
+const EVENT_STANDARD: &str = "dia-gateway"; // NEP-297 standard of the gateway events
+const EVENT_VERSION: &str = "1.0.0"; // version of the gateway events, bumped on breaking changes of their data
+const STATE_VERSION: u16 = 1; // layout version of the contract state, see `VersionedGatewayState`
+const DEFAULT_MAX_DATA_KEY_LEN: u32 = 32; // default max length (bytes) of the data_key of a request
+const DEFAULT_MAX_DATA_ITEM_LEN: u32 = 64; // default max length (bytes) of the data_item of a request
+const DEFAULT_MAX_CALLBACK_LEN: u32 = 64; // default max length (bytes) of the callback method of a request
+const DEFAULT_MAX_DELIVERY_RETRIES: u32 = 2; // default deliveries of a request retried after a failed callback
+const MAX_FAILED_DELIVERIES: usize = 10; // failed deliveries kept per client contract, older ones are dropped
+const DEFAULT_SCHEMA_VERSION: u16 = 1; // response schema version of the data_keys registered by default
+/// DIA endpoints served by dia-adapter, registered on initialization: (data_key, description, data_item format)
+const DEFAULT_DATA_KEYS: [(&str, &str, &str); 4] = [
+    ("quotation", "Latest quotation of an asset", "Asset symbol, e.g. BTC"),
+    ("supply", "Circulating supply of an asset", "Asset symbol, e.g. BTC"),
+    ("volume", "Trade volume of an exchange", "Exchange name, e.g. Binance"),
+    ("symbols", "Symbols of the assets quoted by DIA", "Ignored, can be empty"),
+];
+
+/// Lifecycle of a request, Pending, Claimed and Delivering requests are still open
+#[derive(Serialize, Deserialize, BorshDeserialize, BorshSerialize, Debug, PartialEq, Clone, Copy)]
+#[serde(crate = "near_sdk::serde")]
+pub enum RequestStatus {
+    Pending, /* Waiting for an adapter */
+    Claimed, /* An adapter is serving it */
+    Fulfilled, /* Data was delivered */
+    Failed, /* The adapter could not serve it */
+    Expired, /* Nobody served it in time */
+    Cancelled, /* Withdrawn before being served */
+    Delivering, /* The gateway is calling the callback, settled by the outcome of the call */
+}
+
+impl RequestStatus {
+    /// Returns true while the request is waiting to be served
+    pub fn is_open(&self) -> bool {
+        return *self == RequestStatus::Pending || *self == RequestStatus::Claimed || *self == RequestStatus::Delivering
+    }
+
+    /// Allowed transitions, final statuses can not be left
+    pub fn can_transition_to(&self, next: RequestStatus) -> bool {
+        return match (self, next) {
+            (RequestStatus::Pending, RequestStatus::Pending) => false,
+            (RequestStatus::Pending, _) => true,
+            (RequestStatus::Claimed, RequestStatus::Claimed) => false,
+            (RequestStatus::Claimed, RequestStatus::Cancelled) => false,
+            (RequestStatus::Claimed, _) => true,
+            (RequestStatus::Delivering, RequestStatus::Claimed) => true,
+            (RequestStatus::Delivering, RequestStatus::Fulfilled) => true,
+            (RequestStatus::Delivering, RequestStatus::Failed) => true,
+            (RequestStatus::Delivering, RequestStatus::Expired) => true,
+            _ => false
+        }
+    }
+}
+
+/// Gateway roles, granted by the owner. The `owner_id` account always has the Owner role
+#[derive(Serialize, Deserialize, BorshDeserialize, BorshSerialize, Debug, PartialEq, Clone, Copy)]
+#[serde(crate = "near_sdk::serde")]
+pub enum Role {
+    Owner, /* Configures the gateway and manages roles */
+    Operator, /* Adapter keys, serve requests and update prices */
+    Pauser, /* Emergency stop */
+}
+
+/// Origin of a request deposit, refunds are returned the same way
+#[derive(Serialize, Deserialize, BorshDeserialize, BorshSerialize, Debug, PartialEq, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub enum Payment {
+    Deposit, /* NEAR attached to the request */
+    Credit, /* NEAR drawn from the prepaid credit of the originating contract */
+    Token(AccountId), /* NEP-141 token contract used with `ft_transfer_call` */
+}
 
 /// Request dto, same data structure used for storage and sharing
 #[derive(Serialize, Deserialize, BorshDeserialize, BorshSerialize, Debug, PartialEq, Clone)]
@@ -20,15 +111,403 @@ pub struct Request {
     pub data_key: String, /* Dia api to request */
     pub data_item: String, /* Data to filter the requested result */
     pub callback: String, /* Endpoint where data will be received */
+    pub seq: U64, /* Gateway wide sequence number, assigned by the gateway in arrival order */
+    pub status: RequestStatus, /* Current lifecycle status */
+    pub deposit: U128, /* Deposit attached by the originating contract */
+    pub created_at: U64, /* Block timestamp (ns) of the request */
+    pub expires_at: U64, /* Timestamp (ns) after which the request can no longer be served */
+    pub payment: Payment, /* How the deposit was paid */
+    pub storage_deposit: U128, /* Storage cost paid with the request, returned when the record is deleted */
+}
+
+impl Request {
+    /// Returns true once the request deadline has passed
+    pub fn is_expired(&self) -> bool {
+        return env::block_timestamp() >= self.expires_at.0
+    }
+
+    /// Returns true once a delivery was not settled by `on_fulfill` within the grace period
+    /// after the expiration, so the request can be closed as expired
+    pub fn is_delivery_stuck(&self) -> bool {
+        return self.status == RequestStatus::Delivering
+            && env::block_timestamp() >= self.expires_at.0.saturating_add(DELIVERY_GRACE_PERIOD)
+    }
+}
+
+/// Storage key of a request: (originating contract account id, originating contract specific id)
+pub type RequestKey = (AccountId, u128);
+
+/// Page of requests in arrival order, `next_cursor` is `None` once the last page was reached
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct RequestsPage {
+    pub requests: Vec<Request>,
+    pub next_cursor: Option<U64>,
+}
+
+/// Request layout before sequence numbers were added, only used by `migrate`
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct LegacyRequest {
+    pub contract_account_id: String,
+    pub request_id: U128,
+    pub data_key: String,
+    pub data_item: String,
+    pub callback: String,
+}
+
+/// Request fee, either fixed in yocto-near or pegged to USD cents and converted with the stored NEAR/USD price
+#[derive(Serialize, Deserialize, BorshDeserialize, BorshSerialize, Debug, PartialEq, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub enum Fee {
+    Yocto(U128),
+    UsdCents(U64),
+}
+
+/// Supported DIA endpoint, requests for data_keys that are not registered or disabled are rejected
+#[derive(Serialize, Deserialize, BorshDeserialize, BorshSerialize, Debug, PartialEq, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct DataKeyInfo {
+    pub data_key: String, /* Dia api to request */
+    pub description: String, /* What the endpoint returns */
+    pub data_item_format: String, /* Expected data_item, e.g. an asset symbol */
+    pub fee: Option<Fee>, /* Fee of the data_key, the default fee if none */
+    pub enabled: bool, /* Disabled data_keys do not accept new requests */
+    pub schema_version: u16, /* Version of the response sent to the callbacks */
+}
+
+/// Max lengths (bytes) of the strings a client stores with a request
+#[derive(Serialize, Deserialize, BorshDeserialize, BorshSerialize, Debug, PartialEq, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct InputLimits {
+    pub max_data_key_len: u32,
+    pub max_data_item_len: u32,
+    pub max_callback_len: u32,
+}
+
+impl Default for InputLimits {
+    fn default() -> Self {
+        return Self {
+            max_data_key_len: DEFAULT_MAX_DATA_KEY_LEN,
+            max_data_item_len: DEFAULT_MAX_DATA_ITEM_LEN,
+            max_callback_len: DEFAULT_MAX_CALLBACK_LEN
+        }
+    }
+}
+
+/// NEAR/USD quotation used to price usd fees, updated by the adapter
+#[derive(Serialize, Deserialize, BorshDeserialize, BorshSerialize, Debug, PartialEq, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct NearUsdPrice {
+    pub price: U128, /* USD per NEAR with 6 decimals */
+    pub updated_at: U64, /* Block timestamp (ns) of the update */
+}
+
+/// Request encoded in the `msg` of a fee token `ft_transfer_call`
+#[derive(Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct FtRequestArgs {
+    pub request_id: U128,
+    pub data_key: String,
+    pub data_item: String,
+    pub callback: String,
+}
+
+/// NEP-141 `ft_transfer` arguments, used to refund token deposits and withdraw token fees
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct FtTransferArgs {
+    pub receiver_id: AccountId,
+    pub amount: U128,
+    pub memo: Option<String>,
+}
+
+/// Arguments of the `on_token_transfer` callback
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct OnTokenTransferArgs {
+    pub token_id: AccountId,
+    pub receiver_id: AccountId,
+    pub amount: U128,
+    pub withdrawal: bool,
+}
+
+/// Arguments of the client callback called by `fulfill`, same format the adapter sends
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct CallbackArgs {
+    pub request_id: U128,
+    pub err: String,
+    pub data: Value,
+}
+
+/// Arguments of the `on_fulfill` callback
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct OnFulfillArgs {
+    pub contract_id: AccountId,
+    pub request_id: U128,
+    pub operator_id: AccountId,
+}
+
+/// Delivery made by `fulfill` that the client callback did not accept, the response sent is in the
+/// arguments of the `fulfill` transaction. The runtime only reports that the call failed, a panic
+/// and running out of gas can not be told apart
+#[derive(Serialize, Deserialize, BorshDeserialize, BorshSerialize, Debug, PartialEq, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct FailedDelivery {
+    pub request_id: U128,
+    pub callback: String,
+    pub attempts: u32, /* Failed deliveries of the request */
+    pub failed_at: U64, /* Timestamp (ns) of the last failure */
+}
+
+/// Fees earned by an adapter operator for the requests it served
+#[derive(Serialize, Deserialize, BorshDeserialize, BorshSerialize, Debug, PartialEq, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct OperatorEarnings {
+    pub served_requests: U64, /* Amount of fulfilled requests, paid with any token */
+    pub earned: U128, /* Sum of the NEAR deposits released to the operator (yocto-near) */
+}
+
+impl Default for OperatorEarnings {
+    fn default() -> Self {
+        return Self {
+            served_requests: U64::from(0),
+            earned: U128::from(0)
+        }
+    }
+}
+
+/// NEP-145 storage balance of an account, `available` can be withdrawn
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct StorageBalance {
+    pub total: U128,
+    pub available: U128,
+}
+
+/// NEP-145 storage balance bounds, there is no max
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct StorageBalanceBounds {
+    pub min: U128,
+    pub max: Option<U128>,
+}
+
+/// Gateway state changes, logged as NEP-297 `EVENT_JSON:` events so indexers can follow the
+/// gateway without polling. `data` is the variant content
+#[derive(Serialize, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+#[serde(tag = "event", content = "data", rename_all = "snake_case")]
+pub enum GatewayEvent {
+    /// New request, with its sequence number, fee and storage deposit
+    Request(Request),
+    /// A request changed its status, `account_id` is the caller (the operator for fulfilled requests)
+    RequestStatus { contract_id: AccountId, request_id: U128, status: RequestStatus, account_id: AccountId },
+    /// A closed or expired request record was deleted
+    RequestDeleted { contract_id: AccountId, request_id: U128 },
+    /// Fee of a data_key, or the default fee when `data_key` is null. A null fee restores the default
+    FeeUpdate { data_key: Option<String>, fee: Option<Fee> },
+    /// Fee of a NEP-141 fee token, null once the token is no longer accepted
+    FeeTokenUpdate { token_id: AccountId, fee: Option<U128> },
+    /// Registered, changed or (null info) removed data_key
+    DataKeyUpdate { data_key: String, info: Option<DataKeyInfo> },
+    /// Symbols added to or removed from the valid data_items of a data_key
+    SymbolsUpdate { data_key: String, added: Vec<String>, removed: Vec<String>, symbols_count: u64 },
+    /// Time to live (ns) of a data_key, or the default one when `data_key` is null
+    TtlUpdate { data_key: Option<String>, ttl: Option<U64> },
+    /// Input limits of new requests
+    InputLimitsUpdate(InputLimits),
+    /// Conversion settings of usd fees
+    UsdPricingUpdate { price_slippage_bps: u32, max_price_age: U64 },
+    /// NEAR/USD quotation stored by an operator
+    NearUsdPriceUpdate(NearUsdPrice),
+    /// Outcome of a delivery made by `fulfill`, `attempt` starts at 1
+    Delivery { contract_id: AccountId, request_id: U128, attempt: u32, delivered: bool },
+    /// Deliveries of a request retried after a failed callback
+    DeliveryRetriesUpdate { retries: u32 },
+    /// Max bounty paid for every purged request
+    PurgeBountyUpdate { bounty: U128 },
+    /// Prepaid credit balance of an account after a deposit or withdrawal
+    CreditUpdate { account_id: AccountId, credit: U128 },
+    /// NEP-145 storage balance of an account, null once unregistered
+    StorageBalanceUpdate { account_id: AccountId, balance: Option<U128> },
+    /// Collected fees withdrawn by the owner
+    FeesWithdrawn { receiver_id: AccountId, amount: U128 },
+    /// Collected token fees withdrawn by the owner
+    TokenFeesWithdrawn { token_id: AccountId, receiver_id: AccountId, amount: U128 },
+    /// A token refund or withdrawal was rejected by the token contract, refunds are kept for `claim_token_refund`
+    TokenTransferFailed { token_id: AccountId, receiver_id: AccountId, amount: U128 },
+    RoleGranted { account_id: AccountId, role: Role },
+    RoleRevoked { account_id: AccountId, role: Role },
+    Paused { account_id: AccountId },
+    Unpaused { account_id: AccountId },
+    /// Ownership proposal, null once cancelled
+    OwnerProposed { owner_id: AccountId, proposed_owner_id: Option<AccountId> },
+    OwnershipTransferred { previous_owner_id: AccountId, owner_id: AccountId },
+    /// New code deployed by the owner, `code_hash` is the hex encoded sha256 of the code
+    Upgrade { code_hash: String },
+    /// State migrated to a new layout
+    Migrate { state_version: u16 },
+}
+
+/// NEP-297 log envelope
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+struct EventLog<'a> {
+    standard: &'static str,
+    version: &'static str,
+    #[serde(flatten)]
+    event: &'a GatewayEvent,
+}
+
+impl GatewayEvent {
+    /// Returns the NEP-297 log line of the event
+    pub fn to_log(&self) -> String {
+        let log = EventLog {
+            standard: EVENT_STANDARD,
+            version: EVENT_VERSION,
+            event: self
+        };
+        return format!("EVENT_JSON:{}", near_sdk::serde_json::to_string(&log).unwrap())
+    }
+
+    /// Writes the event to the logs of the current receipt
+    pub fn emit(&self) {
+        env::log(self.to_log().as_bytes());
+    }
+}
+
+/// NEAR fees accounting of the gateway, in yocto-near
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct TreasuryStats {
+    pub collected: U128, /* Fees of fulfilled requests */
+    pub refunded: U128, /* Deposits returned to clients */
+    pub withdrawn: U128, /* Fees withdrawn by the owner */
+    pub withdrawable: U128, /* Collected fees not withdrawn yet */
+    pub escrowed: U128, /* Deposits of open requests */
+    pub credits: U128, /* Prepaid credit of all clients */
+    pub storage_balances: U128, /* NEP-145 storage balances of all clients */
+}
+
+/// Layout of the contract state before requests were moved into persistent collections,
+/// only used by `migrate`
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct LegacyDiaApiGatewayContract {
+    pub owner_id: AccountId,
+    pub requests: Vec<LegacyRequest>
+}
+
+/// Every layout the contract state was stored with, `migrate` converts any of them to the current one
+pub enum VersionedGatewayState {
+    V0(LegacyDiaApiGatewayContract), /* Requests in a `Vec`, deployed before the state had a version */
+    V1(Box<DiaApiGatewayContract>), /* Current layout */
+}
+
+impl VersionedGatewayState {
+    /// Reads the stored state, trying the layouts from the newest to the oldest
+    pub fn read() -> Self {
+        let state = env::storage_read(b"STATE").expect("Nothing to migrate");
+        if let Ok(contract) = DiaApiGatewayContract::try_from_slice(&state) {
+            if contract.state_version == STATE_VERSION {
+                return VersionedGatewayState::V1(Box::new(contract));
+            }
+        }
+        if let Ok(legacy) = LegacyDiaApiGatewayContract::try_from_slice(&state) {
+            return VersionedGatewayState::V0(legacy);
+        }
+        env::panic(b"Unknown state layout")
+    }
+
+    /// Owner of the stored state, the only account (besides the contract itself) allowed to migrate it
+    pub fn owner_id(&self) -> &AccountId {
+        return match self {
+            VersionedGatewayState::V0(legacy) => &legacy.owner_id,
+            VersionedGatewayState::V1(contract) => &contract.owner_id
+        }
+    }
 }
 
 #[near_bindgen]
 #[derive(BorshDeserialize, BorshSerialize)]
 pub struct DiaApiGatewayContract {
+    /// Layout version of the state, always `STATE_VERSION` once migrated
+    pub state_version: u16,
     /// The Account Id of the owner of the contract
     pub owner_id: AccountId,
-    /// Persistent storage of the requests, completed requests are deleted
-    pub requests: Vec<Request>
+    /// Persistent storage of the requests keyed by (contract_account_id, request_id), requests
+    /// are kept after being served so their final status can be queried
+    pub requests: UnorderedMap<RequestKey, Request>,
+    /// Deliveries made by `fulfill` for every open request with failed deliveries, checked against `max_delivery_retries`
+    pub delivery_attempts: LookupMap<RequestKey, u32>,
+    /// Latest failed deliveries of every client contract, at most `MAX_FAILED_DELIVERIES` per account,
+    /// only kept so client teams can debug their callbacks
+    pub failed_deliveries: LookupMap<AccountId, Vec<FailedDelivery>>,
+    /// Failed deliveries of a request that can be retried before it fails
+    pub max_delivery_retries: u32,
+    /// Pending and claimed requests in arrival order, keyed by sequence number
+    pub queue: TreeMap<u64, RequestKey>,
+    /// Stored requests keyed by (status, sequence number), lists the requests of a status in arrival order
+    pub status_index: TreeMap<(u8, u64), RequestKey>,
+    /// Sequence number of the last received request
+    pub last_seq: u64,
+    /// Time (ns) requests wait to be served before expiring, unless overridden for their data_key
+    pub default_ttl: u64,
+    /// Per data_key request time to live overrides (ns)
+    pub ttls: LookupMap<String, u64>,
+    /// Max amount (yocto-near) paid to the caller of `purge_expired` for every purged request
+    pub purge_bounty: u128,
+    /// Sum of the deposits of open requests, locked until they are fulfilled or refunded
+    pub escrowed: u128,
+    /// Fees credited to every operator that fulfilled requests
+    pub operator_earnings: LookupMap<AccountId, OperatorEarnings>,
+    /// Fee of requests without a data_key specific fee
+    pub default_fee: Fee,
+    /// Registry of the supported data_keys, with their fee
+    pub data_keys: UnorderedMap<String, DataKeyInfo>,
+    /// Last NEAR/USD quotation, required to charge usd fees
+    pub near_usd_price: Option<NearUsdPrice>,
+    /// Buffer (basis points) added to usd fees to absorb price moves
+    pub price_slippage_bps: u32,
+    /// Max age (ns) of the NEAR/USD quotation to accept requests with usd fees
+    pub max_price_age: u64,
+    /// Whitelisted NEP-141 token contracts and their fee per request (in token units)
+    pub fee_tokens: UnorderedMap<AccountId, u128>,
+    /// Sum of the token deposits of open requests, per token contract
+    pub token_escrowed: LookupMap<AccountId, u128>,
+    /// Token fees of fulfilled requests not withdrawn yet, per token contract
+    pub token_fees: LookupMap<AccountId, u128>,
+    /// Token refunds rejected by the token contract, keyed by (token contract, receiver)
+    pub token_refunds: LookupMap<(AccountId, AccountId), u128>,
+    /// Token deposits credited to every operator, keyed by (operator, token contract)
+    pub operator_token_earnings: LookupMap<(AccountId, AccountId), u128>,
+    /// Prepaid NEAR balances that client contracts use to pay requests without attaching a deposit
+    pub credits: LookupMap<AccountId, u128>,
+    /// Sum of all the prepaid credits
+    pub credits_total: u128,
+    /// NEAR fees of fulfilled requests
+    pub fees_collected: u128,
+    /// NEAR deposits returned to clients
+    pub fees_refunded: u128,
+    /// NEAR fees withdrawn by the owner
+    pub fees_withdrawn: u128,
+    /// Granted roles as (account, role) pairs
+    pub roles: UnorderedSet<(AccountId, Role)>,
+    /// Account proposed as the new owner, waiting for it to accept the ownership
+    pub proposed_owner_id: Option<AccountId>,
+    /// New requests are rejected while paused
+    pub paused: bool,
+    /// Valid data_items as (data_key, symbol) pairs, maintained by the adapter from the DIA symbols list
+    pub symbols: LookupSet<(String, String)>,
+    /// Amount of symbols of every data_key, data_keys without symbols accept any data_item
+    pub symbol_counts: LookupMap<String, u64>,
+    /// Max lengths of the strings stored with every request
+    pub input_limits: InputLimits,
+    /// NEP-145 storage balances of the registered accounts, they pay the storage of their requests
+    pub storage_balances: LookupMap<AccountId, u128>,
+    /// Sum of all the storage balances
+    pub storage_balances_total: u128
 }
 
 impl Default for DiaApiGatewayContract {
@@ -49,179 +528,3020 @@ impl DiaApiGatewayContract {
             env::is_valid_account_id(owner_id.as_bytes()),
             "The owner account ID is invalid"
         );
-        return Self {
-            owner_id,
-            requests: Vec::new()
-        };
+        return Self::internal_new(owner_id);
+    }
+
+    /// Converts the state stored by any previous version of the contract to the current layout,
+    /// must be called by the owner or the contract account right after deploying the new code.
+    /// Migrating a state that already has the current layout changes nothing
+    #[init]
+    pub fn migrate() -> Self {
+        let state = VersionedGatewayState::read();
+        let predecessor = env::predecessor_account_id();
+        assert!(
+            predecessor == *state.owner_id() || predecessor == env::current_account_id(),
+            "Can only be called by the owner"
+        );
+        GatewayEvent::Migrate { state_version: STATE_VERSION }.emit();
+        return match state {
+            VersionedGatewayState::V0(legacy) => {
+                let mut contract = Self::internal_new(legacy.owner_id);
+                for request in legacy.requests {
+                    /* The legacy `Vec` accepted repeated ids, the first request is kept and the deposit
+                    of the others is returned to the client's prepaid credit */
+                    if contract.requests.get(&(request.contract_account_id.clone(), request.request_id.0)).is_some() {
+                        contract.internal_refund(request.contract_account_id, &Payment::Credit, DEPOSIT_FOR_REQUEST);
+                        continue;
+                    }
+                    contract.internal_add_request(Request {
+                        contract_account_id: request.contract_account_id,
+                        request_id: request.request_id,
+                        data_key: request.data_key,
+                        data_item: request.data_item,
+                        callback: request.callback,
+                        seq: U64::from(0),
+                        status: RequestStatus::Pending,
+                        deposit: U128::from(DEPOSIT_FOR_REQUEST),
+                        created_at: U64::from(0),
+                        expires_at: U64::from(0),
+                        payment: Payment::Deposit,
+                        storage_deposit: U128::from(0)
+                    });
+                }
+                contract
+            },
+            VersionedGatewayState::V1(contract) => *contract
+        }
     }
 
     /******************/
     /* Client methods */
     /******************/
+    /// Stores a new request, the caller pays the data_key fee plus the storage of the request.
+    /// Accounts registered with `storage_deposit` pay the storage from their storage balance.
+    /// Any deposit above the required one is refunded to the caller, without an attached deposit
+    /// the payment is drawn from the caller's prepaid credit. Returns the amount charged (yocto-near)
     #[payable]
-    pub fn request(&mut self, request_id: U128, data_key: String, data_item: String, callback: String){
-        /* Check that deposit (in yocto-near) is enough */
-        if DEPOSIT_FOR_REQUEST>0 {
-            let attached_deposit = env::attached_deposit();
-            assert!(attached_deposit >= DEPOSIT_FOR_REQUEST,
-                "The required attached deposit is {}, but the given attached deposit is {}",
-                DEPOSIT_FOR_REQUEST,
-                attached_deposit
-            ); 
-        }
+    pub fn request(&mut self, request_id: U128, data_key: String, data_item: String, callback: String)-> U128{
+        self.assert_not_paused();
+        self.assert_input_limits(&data_key, &data_item, &callback);
+        self.assert_data_key_enabled(&data_key);
+        self.assert_data_item_valid(&data_key, &data_item);
+        let fee = self.get_fee(data_key.clone()).0;
+        let attached_deposit = env::attached_deposit();
+        let payment = if attached_deposit == 0 { Payment::Credit } else { Payment::Deposit };
+        let key = (env::predecessor_account_id(), request_id.0);
         let request = Request{
             contract_account_id: env::predecessor_account_id(),
             request_id,
             data_key,
             data_item,
-            callback: callback
+            callback: callback,
+            seq: U64::from(0),
+            status: RequestStatus::Pending,
+            deposit: U128::from(fee),
+            created_at: U64::from(0),
+            expires_at: U64::from(0),
+            payment: payment.clone(),
+            storage_deposit: U128::from(0)
         };
-        return self.requests.push(request)
+        let storage_used = self.internal_add_request(request);
+        let storage_cost = self.internal_charge_storage(&key, storage_used);
+        let charged = if self.internal_draw_storage_balance(&key.0, storage_cost) { fee } else { fee + storage_cost };
+        if payment == Payment::Credit {
+            self.internal_draw_credit(&key.0, charged);
+        }
+        /* Check that deposit (in yocto-near) is enough */
+        else {
+            assert!(attached_deposit >= charged,
+                "The required attached deposit is {} (fee {} plus unregistered storage), but the given attached deposit is {}",
+                charged,
+                fee,
+                attached_deposit
+            ); 
+        }
+        GatewayEvent::Request(self.requests.get(&key).expect("Request not found")).emit();
+        /* Refund overpayment */
+        if attached_deposit > charged {
+            Promise::new(env::predecessor_account_id()).transfer(attached_deposit - charged);
+        }
+        return U128::from(charged)
     }
 
+    /// NEP-141 receiver, pays a request with a whitelisted fee token. `msg` is the JSON request
+    /// `{request_id, data_key, data_item, callback}`, the tokens above the token fee are returned.
+    /// The storage of the request is drawn from the sender's storage balance, or its prepaid
+    /// credit if it is not registered
+    pub fn ft_on_transfer(&mut self, sender_id: AccountId, amount: U128, msg: String)-> PromiseOrValue<U128>{
+        self.assert_not_paused();
+        let token_id = env::predecessor_account_id();
+        let fee = self.fee_tokens.get(&token_id).expect("The token is not accepted to pay fees");
+        assert!(amount.0 >= fee,
+            "The required token amount is {}, but the given amount is {}",
+            fee,
+            amount.0
+        );
+        let args: FtRequestArgs = near_sdk::serde_json::from_str(&msg).expect("The message is not a valid request");
+        self.assert_input_limits(&args.data_key, &args.data_item, &args.callback);
+        self.assert_data_key_enabled(&args.data_key);
+        self.assert_data_item_valid(&args.data_key, &args.data_item);
+        let request = Request{
+            contract_account_id: sender_id,
+            request_id: args.request_id,
+            data_key: args.data_key,
+            data_item: args.data_item,
+            callback: args.callback,
+            seq: U64::from(0),
+            status: RequestStatus::Pending,
+            deposit: U128::from(fee),
+            created_at: U64::from(0),
+            expires_at: U64::from(0),
+            payment: Payment::Token(token_id),
+            storage_deposit: U128::from(0)
+        };
+        let key = (request.contract_account_id.clone(), request.request_id.0);
+        let storage_used = self.internal_add_request(request);
+        let storage_cost = self.internal_charge_storage(&key, storage_used);
+        if !self.internal_draw_storage_balance(&key.0, storage_cost) {
+            self.internal_draw_credit(&key.0, storage_cost);
+        }
+        GatewayEvent::Request(self.requests.get(&key).expect("Request not found")).emit();
+        /* Unused tokens are returned by the token contract */
+        return PromiseOrValue::Value(U128::from(amount.0 - fee))
+    }
 
-    /***********************/
-    /* Dia adapter methods */
-    /***********************/
+    /// Adds the attached deposit to the caller's prepaid credit
+    #[payable]
+    pub fn deposit_credit(&mut self)-> U128{
+        assert!(env::attached_deposit() > 0, "Requires a deposit to add to the credit");
+        let account_id = env::predecessor_account_id();
+        let credit = self.credits.get(&account_id).unwrap_or(0) + env::attached_deposit();
+        self.credits.insert(&account_id, &credit);
+        self.credits_total += env::attached_deposit();
+        GatewayEvent::CreditUpdate { account_id, credit: credit.into() }.emit();
+        return credit.into()
+    }
 
-    pub fn get_pending_requests_count(&self)-> u64{
-        return self.requests.len() as u64
+    /// Transfers `amount` of the caller's prepaid credit back to it
+    pub fn withdraw_credit(&mut self, amount: U128)-> Promise{
+        let account_id = env::predecessor_account_id();
+        let credit = self.credits.get(&account_id).unwrap_or(0);
+        assert!(credit >= amount.0,
+            "The credit balance is {}, can not withdraw {}",
+            credit,
+            amount.0
+        );
+        if credit == amount.0 {
+            self.credits.remove(&account_id);
+        }
+        else {
+            self.credits.insert(&account_id, &(credit - amount.0));
+        }
+        self.credits_total -= amount.0;
+        GatewayEvent::CreditUpdate { account_id: account_id.clone(), credit: U128::from(credit - amount.0) }.emit();
+        return Promise::new(account_id).transfer(amount.0);
     }
 
-    pub fn get_pending_requests(&self)-> Vec<Request>{
-        return self.requests.clone()
+    /// Returns the prepaid credit (yocto-near) of an account
+    pub fn get_credit(&self, account_id: AccountId)-> U128{
+        return self.credits.get(&account_id).unwrap_or(0).into()
     }
 
-    pub fn remove(&mut self, contract_id: String, request_id: U128){
-        /* Prevent other people from removing pending requests */
-        assert_eq!(
-            env::predecessor_account_id(),
-            self.owner_id,
-            "Can only be called by the owner"
-        );
-        let index = self.requests.iter().position(|request| {
-            request.request_id == request_id && request.contract_account_id == contract_id
-        }).unwrap();
-        self.requests.remove(index);
+    /// Returns the whitelisted fee tokens and their fee per request (in token units)
+    pub fn get_fee_tokens(&self)-> Vec<(AccountId, U128)>{
+        return self.fee_tokens.iter().map(|(token_id, fee)| (token_id, U128::from(fee))).collect()
     }
-}
 
-/**************/
-/* Unit tests */
-/**************/
+    /// Returns the fee (yocto-near) that has to be attached to request the given data_key,
+    /// usd fees are converted with the current NEAR/USD price
+    pub fn get_fee(&self, data_key: String)-> U128{
+        return self.internal_fee_to_yocto(&self.get_fee_config(data_key)).into()
+    }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use near_sdk::MockedBlockchain;
-    use near_sdk::{testing_env, VMContext};
+    /// Returns the fee configured for the given data_key
+    pub fn get_fee_config(&self, data_key: String)-> Fee{
+        return self.internal_get_data_key(&data_key).fee.unwrap_or_else(|| self.default_fee.clone())
+    }
 
+    /// Returns the enabled data_keys, the DIA endpoints that can be requested
+    pub fn get_supported_data_keys(&self)-> Vec<DataKeyInfo>{
+        return self.data_keys.values()
+            .filter(|info| info.enabled)
+            .collect()
+    }
 
-    static OWNER: &str = "testowner.testnet";
-    static TEST_ACCOUNT: &str = "client.testnet";
+    /// Returns a registered data_key, enabled or not
+    pub fn get_data_key(&self, data_key: String)-> Option<DataKeyInfo>{
+        return self.data_keys.get(&data_key)
+    }
 
-    /// Set the contract context
-    pub fn initialize(){
-        let context = get_context(String::from(TEST_ACCOUNT), 10);                    
-        testing_env!(context); 
+    /// Returns true if a request for the data_item would be accepted, data_keys without
+    /// symbols accept any data_item
+    pub fn is_valid_data_item(&self, data_key: String, data_item: String)-> bool{
+        return self.get_symbols_count(data_key.clone()) == 0 || self.symbols.contains(&(data_key, data_item))
     }
-    
-    /// Creates a contract
-    pub fn create_contract() -> DiaApiGatewayContract{
-        let contract = super::DiaApiGatewayContract::new(String::from(OWNER));
-        return contract;
+
+    /// Returns the amount of valid symbols of a data_key
+    pub fn get_symbols_count(&self, data_key: String)-> u64{
+        return self.symbol_counts.get(&data_key).unwrap_or(0)
     }
 
-    ///Creates a request as a client and returns the expected saved value
-    pub fn create_request(contract: &mut DiaApiGatewayContract) -> Request{
-        contract.request(U128::from(1231223), String::from("quotation"), String::from("BTC"), String::from("callback"));
-        let expected_request = Request{
-            contract_account_id: String::from(TEST_ACCOUNT),
-            request_id: U128::from(1231223),
-            data_key: String::from("quotation"),
-            data_item: String::from("BTC"),
-            callback: String::from("callback")
-        };
-        return expected_request;
+    /// Returns the NEAR/USD quotation used to price usd fees
+    pub fn get_near_usd_price(&self)-> Option<NearUsdPrice>{
+        return self.near_usd_price.clone()
     }
 
-    /// Defines the context for the contract
-    fn get_context(predecessor_account_id: String, storage_usage: u64) -> VMContext {
-        VMContext {
-            current_account_id: OWNER.to_string(),
-            signer_account_id: OWNER.to_string(),
-            signer_account_pk: vec![0, 1, 2],
-            predecessor_account_id,
-            input: vec![],
-            block_index: 0,
-            block_timestamp: 0,
-            account_balance: 0,
-            account_locked_balance: 0,
-            storage_usage,
-            attached_deposit: 0,
-            prepaid_gas: 10u64.pow(18),
-            random_seed: vec![0, 1, 2],
-            is_view: false,
-            output_data_receivers: vec![],
-            epoch_height: 19,
-        }
+    /// Cancels a pending request of the calling contract and refunds its deposit,
+    /// requests already claimed by an adapter can not be cancelled
+    pub fn cancel_request(&mut self, request_id: U128){
+        let key = (env::predecessor_account_id(), request_id.0);
+        let request = self.requests.get(&key).expect("Request not found");
+        assert!(
+            request.status == RequestStatus::Pending,
+            "Only pending requests can be cancelled, request is {:?}",
+            request.status
+        );
+        self.internal_update_status(&key, RequestStatus::Cancelled, &env::predecessor_account_id());
     }
 
-    #[test]
-    fn test_creation() {
-        initialize();
-        let contract = create_contract();
-        assert_eq!(contract.owner_id, String::from(OWNER), "Owner is different from the expected");
+    /// Marks an expired request of the calling contract as `Expired` and refunds its deposit
+    pub fn refund_expired_request(&mut self, request_id: U128){
+        let key = (env::predecessor_account_id(), request_id.0);
+        let request = self.requests.get(&key).expect("Request not found");
+        assert!(request.is_expired(), "Request has not expired yet");
+        self.internal_update_status(&key, RequestStatus::Expired, &env::predecessor_account_id());
     }
 
-    #[test]
-    #[should_panic]
-    fn test_default_creation() {
-        initialize();
-        <DiaApiGatewayContract as Default>::default();
+    /// Deletes a served, failed, expired or cancelled request of the calling contract and returns
+    /// its storage deposit the way the request was paid. Returns the amount refunded
+    pub fn delete_request(&mut self, request_id: U128)-> U128{
+        let key = (env::predecessor_account_id(), request_id.0);
+        let request = self.requests.get(&key).expect("Request not found");
+        assert!(
+            !request.status.is_open(),
+            "Only closed requests can be deleted, request is {:?}",
+            request.status
+        );
+        self.requests.remove(&key);
+        self.status_index.remove(&(request.status as u8, request.seq.0));
+        self.internal_remove_failed_delivery(&key);
+        GatewayEvent::RequestDeleted { contract_id: key.0.clone(), request_id }.emit();
+        self.internal_refund_storage(key.0, &request.payment, request.storage_deposit.0);
+        return request.storage_deposit
     }
 
-    #[test]
-    fn test_client_methods(){
-        initialize();
-        let mut contract = create_contract();
-        let expected_request = create_request(&mut contract);
-        
-        if let Some(request) = contract.requests.get(0) {
-            assert_eq!(expected_request, *request, "Saved request has wrong field values");
-        }
-        else{
-            panic!("Request not saved");
-        }
+    /// Returns the time to live (ns) of new requests for the given data_key
+    pub fn get_ttl(&self, data_key: String)-> U64{
+        return self.ttls.get(&data_key).unwrap_or(self.default_ttl).into()
     }
 
-    #[test]
-    fn test_adapter_methods(){
-        initialize();
-        /* Create a request as a client */
-        let mut contract = create_contract();
-        let expected_request = create_request(&mut contract);
-        /* Change context to match a method called by the adapter */
-        let context = get_context(String::from(OWNER), 10);                    
-        testing_env!(context); 
-      
-        println!("Testing 'get_pending_requests_count' method");
-        let pending_requests_count = contract.get_pending_requests_count();
-        assert_eq!(pending_requests_count, 1 as u64, "Wrong value ({}) in pending requests", pending_requests_count);
 
-        println!("Testing 'get_pending_requests' method");
-        let pending_requests = contract.get_pending_requests();
-        if let Some(request) = pending_requests.get(0) {
-            assert_eq!(expected_request, *request, "Method 'get_pending_requests' returns wrong data");
-        }
-        else{
-            panic!("Method 'get_pending_requests' dont returns data");
-        }
+    /***********************/
+    /* Dia adapter methods */
+    /***********************/
 
-        println!("Testing 'remove' method");
-        contract.remove(String::from(TEST_ACCOUNT), U128::from(1231223));
+    pub fn get_pending_requests_count(&self)-> u64{
+        return self.queue.len()
     }
-    
+
+    /// Returns up to `limit` pending requests in arrival order, skipping the first `from_index`
+    pub fn get_pending_requests(&self, from_index: u64, limit: u64)-> Vec<Request>{
+        return self.queue.iter()
+            .skip(from_index as usize)
+            .take(std::cmp::min(limit, MAX_PAGE_LIMIT) as usize)
+            .map(|(_, key)| self.requests.get(&key).expect("Queued request not found"))
+            .collect()
+    }
+
+    /// Returns up to `limit` pending requests in arrival order, starting after the given cursor
+    /// (from the beginning if none). Use the returned `next_cursor` to request the next page,
+    /// cursors are request sequence numbers
+    pub fn get_pending_requests_after(&self, cursor: Option<U64>, limit: u64)-> RequestsPage{
+        let limit = std::cmp::min(limit, MAX_PAGE_LIMIT) as usize;
+        let entries: Vec<(u64, RequestKey)> = match cursor {
+            Some(cursor) => self.queue.iter_from(cursor.0).take(limit).collect(),
+            None => self.queue.iter().take(limit).collect()
+        };
+        let next_cursor = match entries.last() {
+            Some((seq, _)) if self.queue.higher(seq).is_some() => Some(U64::from(*seq)),
+            _ => None
+        };
+        return RequestsPage {
+            requests: entries.iter()
+                .map(|(_, key)| self.requests.get(key).expect("Queued request not found"))
+                .collect(),
+            next_cursor
+        }
+    }
+
+    /// Returns the sequence number of the last received request
+    pub fn get_last_seq(&self)-> U64{
+        return self.last_seq.into()
+    }
+
+    /// Returns up to `limit` pending requests with a sequence number greater than `seq`, in
+    /// arrival order. Adapters can poll with the last processed sequence number as a checkpoint
+    pub fn get_requests_since(&self, seq: U64, limit: u64)-> Vec<Request>{
+        return self.queue.iter_from(seq.0)
+            .take(std::cmp::min(limit, MAX_PAGE_LIMIT) as usize)
+            .map(|(_, key)| self.requests.get(&key).expect("Queued request not found"))
+            .collect()
+    }
+
+    /// Marks a request as served, kept for dia-adapter compatibility. Same as
+    /// `update_request_status` with the `Fulfilled` status
+    pub fn remove(&mut self, contract_id: String, request_id: U128){
+        self.update_request_status(contract_id, request_id, RequestStatus::Fulfilled);
+    }
+
+    /// Sets the max lengths (bytes) of the data_key, data_item and callback of new requests
+    pub fn set_input_limits(&mut self, max_data_key_len: u32, max_data_item_len: u32, max_callback_len: u32){
+        self.assert_role(Role::Owner);
+        assert!(
+            max_data_key_len > 0 && max_data_item_len > 0 && max_callback_len > 0,
+            "The max lengths must be greater than zero"
+        );
+        self.input_limits = InputLimits {
+            max_data_key_len,
+            max_data_item_len,
+            max_callback_len
+        };
+        GatewayEvent::InputLimitsUpdate(self.input_limits.clone()).emit();
+    }
+
+    /// Sets how many times the delivery of a request is retried after its callback failed
+    pub fn set_max_delivery_retries(&mut self, retries: u32){
+        self.assert_role(Role::Owner);
+        self.max_delivery_retries = retries;
+        GatewayEvent::DeliveryRetriesUpdate { retries }.emit();
+    }
+
+    /// Sets the time to live (ns) of requests without a data_key specific value
+    pub fn set_default_ttl(&mut self, ttl: U64){
+        self.assert_role(Role::Owner);
+        assert!(ttl.0 > 0, "The TTL must be greater than zero");
+        self.default_ttl = ttl.0;
+        GatewayEvent::TtlUpdate { data_key: None, ttl: Some(ttl) }.emit();
+    }
+
+    /// Sets the time to live (ns) of new requests for a data_key, `null` restores the default
+    pub fn set_ttl(&mut self, data_key: String, ttl: Option<U64>){
+        self.assert_role(Role::Owner);
+        match ttl {
+            Some(ttl) => {
+                assert!(ttl.0 > 0, "The TTL must be greater than zero");
+                self.ttls.insert(&data_key, &ttl.0);
+            },
+            None => {
+                self.ttls.remove(&data_key);
+            }
+        }
+        GatewayEvent::TtlUpdate { data_key: Some(data_key), ttl }.emit();
+    }
+
+    /// Sets the fee (yocto-near) of requests without a data_key specific fee
+    pub fn set_default_fee(&mut self, fee: U128){
+        self.assert_role(Role::Owner);
+        self.default_fee = Fee::Yocto(fee);
+        GatewayEvent::FeeUpdate { data_key: None, fee: Some(self.default_fee.clone()) }.emit();
+    }
+
+    /// Sets the fee (USD cents) of requests without a data_key specific fee
+    pub fn set_default_fee_usd(&mut self, usd_cents: U64){
+        self.assert_role(Role::Owner);
+        self.default_fee = Fee::UsdCents(usd_cents);
+        GatewayEvent::FeeUpdate { data_key: None, fee: Some(self.default_fee.clone()) }.emit();
+    }
+
+    /// Sets the fee (yocto-near) of requests for a data_key, `null` restores the default
+    pub fn set_fee(&mut self, data_key: String, fee: Option<U128>){
+        self.assert_role(Role::Owner);
+        let mut info = self.internal_get_data_key(&data_key);
+        info.fee = fee.map(Fee::Yocto);
+        self.data_keys.insert(&data_key, &info);
+        GatewayEvent::FeeUpdate { data_key: Some(data_key), fee: info.fee }.emit();
+    }
+
+    /// Sets the fee (USD cents) of requests for a data_key
+    pub fn set_fee_usd(&mut self, data_key: String, usd_cents: U64){
+        self.assert_role(Role::Owner);
+        let mut info = self.internal_get_data_key(&data_key);
+        info.fee = Some(Fee::UsdCents(usd_cents));
+        self.data_keys.insert(&data_key, &info);
+        GatewayEvent::FeeUpdate { data_key: Some(data_key), fee: info.fee }.emit();
+    }
+
+    /// Registers a supported data_key or updates its description, new data_keys are enabled
+    /// and charged the default fee
+    pub fn set_data_key(&mut self, data_key: String, description: String, data_item_format: String, schema_version: u16){
+        self.assert_role(Role::Owner);
+        assert!(!data_key.is_empty(), "The data_key can not be empty");
+        let info = match self.data_keys.get(&data_key) {
+            Some(info) => DataKeyInfo { description, data_item_format, schema_version, ..info },
+            None => DataKeyInfo {
+                data_key: data_key.clone(),
+                description,
+                data_item_format,
+                fee: None,
+                enabled: true,
+                schema_version
+            }
+        };
+        self.data_keys.insert(&data_key, &info);
+        GatewayEvent::DataKeyUpdate { data_key, info: Some(info) }.emit();
+    }
+
+    /// Enables or disables new requests for a registered data_key, open requests are still served
+    pub fn set_data_key_enabled(&mut self, data_key: String, enabled: bool){
+        self.assert_role(Role::Owner);
+        let mut info = self.internal_get_data_key(&data_key);
+        info.enabled = enabled;
+        self.data_keys.insert(&data_key, &info);
+        GatewayEvent::DataKeyUpdate { data_key, info: Some(info) }.emit();
+    }
+
+    /// Unregisters a data_key, open requests are still served
+    pub fn remove_data_key(&mut self, data_key: String){
+        self.assert_role(Role::Owner);
+        self.data_keys.remove(&data_key).unwrap_or_else(|| env::panic(format!("Unknown data_key {}", data_key).as_bytes()));
+        GatewayEvent::DataKeyUpdate { data_key, info: None }.emit();
+    }
+
+    /// Accepts a NEP-141 token to pay requests through `ft_transfer_call`, or updates its fee
+    pub fn set_fee_token(&mut self, token_id: AccountId, fee: U128){
+        self.assert_role(Role::Owner);
+        assert!(
+            env::is_valid_account_id(token_id.as_bytes()),
+            "The token account ID is invalid"
+        );
+        self.fee_tokens.insert(&token_id, &fee.0);
+        GatewayEvent::FeeTokenUpdate { token_id, fee: Some(fee) }.emit();
+    }
+
+    /// Stops accepting a fee token, open requests paid with it are still settled in that token
+    pub fn remove_fee_token(&mut self, token_id: AccountId){
+        self.assert_role(Role::Owner);
+        self.fee_tokens.remove(&token_id).expect("The token is not accepted to pay fees");
+        GatewayEvent::FeeTokenUpdate { token_id, fee: None }.emit();
+    }
+
+    /// Sets the buffer (basis points) added to usd fees when converting them to yocto-near
+    pub fn set_price_slippage_bps(&mut self, slippage_bps: u32){
+        self.assert_role(Role::Owner);
+        self.price_slippage_bps = slippage_bps;
+        GatewayEvent::UsdPricingUpdate {
+            price_slippage_bps: self.price_slippage_bps,
+            max_price_age: self.max_price_age.into()
+        }.emit();
+    }
+
+    /// Sets the max age (ns) of the NEAR/USD price to accept requests with usd fees
+    pub fn set_max_price_age(&mut self, max_age: U64){
+        self.assert_role(Role::Owner);
+        self.max_price_age = max_age.0;
+        GatewayEvent::UsdPricingUpdate {
+            price_slippage_bps: self.price_slippage_bps,
+            max_price_age: self.max_price_age.into()
+        }.emit();
+    }
+
+    /// Sets the max bounty (yocto-near) paid for every request deleted by `purge_expired`
+    pub fn set_purge_bounty(&mut self, bounty: U128){
+        self.assert_role(Role::Owner);
+        self.purge_bounty = bounty.0;
+        GatewayEvent::PurgeBountyUpdate { bounty }.emit();
+    }
+
+    /// Stores the NEAR/USD quotation (USD per NEAR with 6 decimals) used to price usd fees
+    pub fn update_near_usd_price(&mut self, price: U128){
+        self.assert_role(Role::Operator);
+        assert!(price.0 > 0, "The price must be greater than zero");
+        let near_usd_price = NearUsdPrice {
+            price,
+            updated_at: U64::from(env::block_timestamp())
+        };
+        self.near_usd_price = Some(near_usd_price.clone());
+        GatewayEvent::NearUsdPriceUpdate(near_usd_price).emit();
+    }
+
+    /// Adds valid data_items to a registered data_key, usually the symbols DIA returns for it.
+    /// Once a data_key has symbols, requests for other data_items are rejected. Returns the
+    /// amount of symbols of the data_key
+    pub fn add_symbols(&mut self, data_key: String, symbols: Vec<String>)-> u64{
+        self.assert_role(Role::Operator);
+        self.internal_get_data_key(&data_key);
+        assert!(symbols.len() <= MAX_SYMBOLS_PER_CALL, "At most {} symbols can be added per call", MAX_SYMBOLS_PER_CALL);
+        let mut count = self.get_symbols_count(data_key.clone());
+        for symbol in symbols.iter() {
+            if self.symbols.insert(&(data_key.clone(), symbol.clone())) {
+                count += 1;
+            }
+        }
+        self.symbol_counts.insert(&data_key, &count);
+        GatewayEvent::SymbolsUpdate { data_key, added: symbols, removed: vec![], symbols_count: count }.emit();
+        return count
+    }
+
+    /// Removes valid data_items of a data_key, a data_key left without symbols accepts any
+    /// data_item. Returns the amount of symbols of the data_key
+    pub fn remove_symbols(&mut self, data_key: String, symbols: Vec<String>)-> u64{
+        self.assert_role(Role::Operator);
+        assert!(symbols.len() <= MAX_SYMBOLS_PER_CALL, "At most {} symbols can be removed per call", MAX_SYMBOLS_PER_CALL);
+        let mut count = self.get_symbols_count(data_key.clone());
+        for symbol in symbols.iter() {
+            if self.symbols.remove(&(data_key.clone(), symbol.clone())) {
+                count -= 1;
+            }
+        }
+        if count == 0 {
+            self.symbol_counts.remove(&data_key);
+        }
+        else {
+            self.symbol_counts.insert(&data_key, &count);
+        }
+        GatewayEvent::SymbolsUpdate { data_key, added: vec![], removed: symbols, symbols_count: count }.emit();
+        return count
+    }
+
+    /// Transfers collected NEAR fees to `receiver_id`. Escrowed deposits, prepaid credits, storage
+    /// balances and the balance staked for storage can never be withdrawn
+    pub fn withdraw_fees(&mut self, receiver_id: AccountId, amount: U128)-> Promise{
+        self.assert_role(Role::Owner);
+        assert!(
+            env::is_valid_account_id(receiver_id.as_bytes()),
+            "The receiver account ID is invalid"
+        );
+        let withdrawable = self.fees_collected - self.fees_withdrawn;
+        assert!(amount.0 <= withdrawable,
+            "The withdrawable fees are {}, can not withdraw {}",
+            withdrawable,
+            amount.0
+        );
+        let reserved = self.escrowed + self.credits_total + self.storage_balances_total + env::storage_usage() as u128 * STORAGE_PRICE_PER_BYTE;
+        let available = env::account_balance().saturating_sub(reserved);
+        assert!(amount.0 <= available,
+            "The available balance is {}, can not withdraw {}",
+            available,
+            amount.0
+        );
+        self.fees_withdrawn += amount.0;
+        GatewayEvent::FeesWithdrawn { receiver_id: receiver_id.clone(), amount }.emit();
+        return Promise::new(receiver_id).transfer(amount.0);
+    }
+
+    /// Transfers collected fees of a NEP-141 token to `receiver_id`, the receiver must be registered
+    /// with the token. A rejected transfer returns the amount to the collected fees
+    pub fn withdraw_token_fees(&mut self, token_id: AccountId, receiver_id: AccountId, amount: U128)-> Promise{
+        self.assert_role(Role::Owner);
+        assert!(
+            env::is_valid_account_id(receiver_id.as_bytes()),
+            "The receiver account ID is invalid"
+        );
+        let collected = self.token_fees.get(&token_id).unwrap_or(0);
+        assert!(amount.0 <= collected,
+            "The withdrawable {} fees are {}, can not withdraw {}",
+            token_id,
+            collected,
+            amount.0
+        );
+        self.token_fees.insert(&token_id, &(collected - amount.0));
+        GatewayEvent::TokenFeesWithdrawn { token_id: token_id.clone(), receiver_id: receiver_id.clone(), amount }.emit();
+        return self.internal_ft_transfer(token_id, receiver_id, amount.0, true);
+    }
+
+    /// Sends again a token refund of the caller that the token contract rejected, e.g. because
+    /// the caller was not registered with the token
+    pub fn claim_token_refund(&mut self, token_id: AccountId)-> Promise{
+        let account_id = env::predecessor_account_id();
+        let amount = self.token_refunds.remove(&(token_id.clone(), account_id.clone()))
+            .expect("There is no failed refund to claim");
+        return self.internal_ft_transfer(token_id, account_id, amount, false);
+    }
+
+    /// Callback of the token refunds and withdrawals. Rejected withdrawals go back to the
+    /// collected token fees, rejected refunds are kept for `claim_token_refund`. Returns true if transferred
+    pub fn on_token_transfer(&mut self, token_id: AccountId, receiver_id: AccountId, amount: U128, withdrawal: bool)-> bool{
+        assert_eq!(env::predecessor_account_id(), env::current_account_id(), "Can only be called by the gateway");
+        assert_eq!(env::promise_results_count(), 1, "Expected the result of the transfer");
+        if let PromiseResult::Successful(_) = env::promise_result(0) {
+            return true
+        }
+        if withdrawal {
+            let collected = self.token_fees.get(&token_id).unwrap_or(0);
+            self.token_fees.insert(&token_id, &(collected + amount.0));
+        }
+        else {
+            let refund_key = (token_id.clone(), receiver_id.clone());
+            let refund = self.token_refunds.get(&refund_key).unwrap_or(0);
+            self.token_refunds.insert(&refund_key, &(refund + amount.0));
+        }
+        GatewayEvent::TokenTransferFailed { token_id, receiver_id, amount }.emit();
+        return false
+    }
+
+    /// Proposes a new owner, the ownership is transferred once the proposed account accepts it
+    pub fn propose_owner(&mut self, new_owner_id: AccountId){
+        self.assert_owner_id();
+        assert!(
+            env::is_valid_account_id(new_owner_id.as_bytes()),
+            "The new owner account ID is invalid"
+        );
+        assert!(new_owner_id != self.owner_id, "The account is already the owner");
+        self.proposed_owner_id = Some(new_owner_id);
+        GatewayEvent::OwnerProposed { owner_id: self.owner_id.clone(), proposed_owner_id: self.proposed_owner_id.clone() }.emit();
+    }
+
+    /// Withdraws the pending ownership proposal
+    pub fn cancel_owner_proposal(&mut self){
+        self.assert_owner_id();
+        assert!(self.proposed_owner_id.is_some(), "There is no pending ownership proposal");
+        self.proposed_owner_id = None;
+        GatewayEvent::OwnerProposed { owner_id: self.owner_id.clone(), proposed_owner_id: None }.emit();
+    }
+
+    /// Called by the proposed account to become the owner. The roles of the previous owner,
+    /// including the Operator role granted on initialization, are revoked so its key can no
+    /// longer act on the gateway. The new owner grants them again if needed
+    pub fn accept_ownership(&mut self){
+        let proposed_owner_id = self.proposed_owner_id.take().expect("There is no pending ownership proposal");
+        assert_eq!(
+            env::predecessor_account_id(),
+            proposed_owner_id,
+            "Can only be called by the proposed owner"
+        );
+        GatewayEvent::OwnershipTransferred {
+            previous_owner_id: self.owner_id.clone(),
+            owner_id: proposed_owner_id.clone()
+        }.emit();
+        for role in [Role::Owner, Role::Operator, Role::Pauser].iter() {
+            if self.roles.remove(&(self.owner_id.clone(), *role)) {
+                GatewayEvent::RoleRevoked { account_id: self.owner_id.clone(), role: *role }.emit();
+            }
+        }
+        self.owner_id = proposed_owner_id;
+    }
+
+    /// Deploys new contract code and migrates the state to its layout in the same batch,
+    /// a failed migration reverts the deployment. Attach enough gas for `migrate`
+    pub fn upgrade(&mut self, code: Base64VecU8)-> Promise{
+        self.assert_owner_id();
+        let code: Vec<u8> = code.into();
+        let code_hash = env::sha256(&code).iter().map(|byte| format!("{:02x}", byte)).collect();
+        GatewayEvent::Upgrade { code_hash }.emit();
+        return Promise::new(env::current_account_id())
+            .deploy_contract(code)
+            .function_call(b"migrate".to_vec(), b"{}".to_vec(), 0, GAS_FOR_MIGRATE);
+    }
+
+    /// Stops accepting new requests, cancellations and refunds keep working
+    pub fn pause(&mut self){
+        self.assert_pauser();
+        assert!(!self.paused, "The gateway is already paused");
+        self.paused = true;
+        GatewayEvent::Paused { account_id: env::predecessor_account_id() }.emit();
+    }
+
+    /// Accepts new requests again
+    pub fn unpause(&mut self){
+        self.assert_pauser();
+        assert!(self.paused, "The gateway is not paused");
+        self.paused = false;
+        GatewayEvent::Unpaused { account_id: env::predecessor_account_id() }.emit();
+    }
+
+    /// Grants a role to an account
+    pub fn add_role(&mut self, account_id: AccountId, role: Role){
+        self.assert_role(Role::Owner);
+        assert!(
+            env::is_valid_account_id(account_id.as_bytes()),
+            "The account ID is invalid"
+        );
+        self.roles.insert(&(account_id.clone(), role));
+        GatewayEvent::RoleGranted { account_id, role }.emit();
+    }
+
+    /// Revokes a role from an account, the Owner role of `owner_id` can not be revoked
+    pub fn revoke_role(&mut self, account_id: AccountId, role: Role){
+        self.assert_role(Role::Owner);
+        assert!(self.roles.remove(&(account_id.clone(), role)), "The account does not have the {:?} role", role);
+        GatewayEvent::RoleRevoked { account_id, role }.emit();
+    }
+
+    /// Moves a request to a new status, panics if the transition is not allowed.
+    /// Deliveries made by `fulfill` are settled by their outcome only
+    pub fn update_request_status(&mut self, contract_id: String, request_id: U128, status: RequestStatus){
+        /* Prevent other people from changing requests */
+        self.assert_role(Role::Operator);
+        let key = (contract_id, request_id.0);
+        let request = self.requests.get(&key).expect("Request not found");
+        assert!(
+            request.status != RequestStatus::Delivering && status != RequestStatus::Delivering,
+            "Deliveries are only managed by fulfill"
+        );
+        self.internal_update_status(&key, status, &env::predecessor_account_id());
+    }
+
+    /// Sends the response of a pending or claimed request to its callback method and settles the
+    /// request with the outcome of the call: `Fulfilled` once delivered. A failed callback is
+    /// recorded and the request stays `Claimed` while it has retries left, then it is `Failed`.
+    /// The callback receives `{request_id, err, data}` with the gateway as predecessor
+    pub fn fulfill(&mut self, contract_id: AccountId, request_id: U128, err: String, data: Value)-> Promise{
+        self.assert_role(Role::Operator);
+        return self.internal_deliver((contract_id, request_id.0), err, data);
+    }
+
+    /// Callback of `fulfill`, settles the request with the outcome of the delivery: the deposit is
+    /// credited to the operator once delivered. Failed deliveries are recorded, the request goes back
+    /// to `Claimed` while it has retries left, then it fails and its deposit is refunded. Returns true if delivered
+    pub fn on_fulfill(&mut self, contract_id: AccountId, request_id: U128, operator_id: AccountId)-> bool{
+        assert_eq!(env::predecessor_account_id(), env::current_account_id(), "Can only be called by the gateway");
+        assert_eq!(env::promise_results_count(), 1, "Expected the result of the delivery");
+        let delivered = matches!(env::promise_result(0), PromiseResult::Successful(_));
+        let key = (contract_id.clone(), request_id.0);
+        /* A stuck delivery may already have been closed as expired */
+        match self.requests.get(&key) {
+            Some(request) if request.status == RequestStatus::Delivering => (),
+            _ => return delivered
+        }
+        let attempt = self.delivery_attempts.get(&key).unwrap_or(0) + 1;
+        GatewayEvent::Delivery { contract_id: contract_id.clone(), request_id, attempt, delivered }.emit();
+        if delivered {
+            /* Only undelivered requests are tracked */
+            if attempt > 1 {
+                self.internal_remove_failed_delivery(&key);
+            }
+            self.internal_update_status(&key, RequestStatus::Fulfilled, &operator_id);
+            return true
+        }
+        /* The list is capped, the attempts of the request are counted apart */
+        let mut failures = self.failed_deliveries.get(&contract_id).unwrap_or_default();
+        failures.retain(|failure| failure.request_id != request_id);
+        let request = self.requests.get(&key).expect("Request not found");
+        failures.push(FailedDelivery {
+            request_id,
+            callback: request.callback,
+            attempts: attempt,
+            failed_at: U64::from(env::block_timestamp())
+        });
+        if failures.len() > MAX_FAILED_DELIVERIES {
+            failures.remove(0);
+        }
+        self.failed_deliveries.insert(&contract_id, &failures);
+        /* Requests with retries left stay claimed, so they can be delivered again */
+        if attempt <= self.max_delivery_retries {
+            self.delivery_attempts.insert(&key, &attempt);
+            self.internal_update_status(&key, RequestStatus::Claimed, &operator_id);
+        }
+        else {
+            self.delivery_attempts.remove(&key);
+            self.internal_update_status(&key, RequestStatus::Failed, &operator_id);
+        }
+        return false
+    }
+
+    /******************/
+    /*  Status views  */
+    /******************/
+
+    /// Returns a request in any status
+    pub fn get_request(&self, contract_id: String, request_id: U128)-> Option<Request>{
+        return self.requests.get(&(contract_id, request_id.0))
+    }
+
+    /// Returns the max lengths of the strings stored with a request
+    pub fn get_input_limits(&self)-> InputLimits{
+        return self.input_limits.clone()
+    }
+
+    /// Returns how many times the delivery of a request is retried after its callback failed
+    pub fn get_max_delivery_retries(&self)-> u32{
+        return self.max_delivery_retries
+    }
+
+    /// Returns the latest failed deliveries to a client contract, oldest first
+    pub fn get_failed_deliveries(&self, account_id: AccountId)-> Vec<FailedDelivery>{
+        return self.failed_deliveries.get(&account_id).unwrap_or_default()
+    }
+
+    /// Returns the max bounty (yocto-near) paid for every request deleted by `purge_expired`
+    pub fn get_purge_bounty(&self)-> U128{
+        return self.purge_bounty.into()
+    }
+
+    /// Returns the sum of the deposits locked by open requests
+    pub fn get_escrowed_total(&self)-> U128{
+        return self.escrowed.into()
+    }
+
+    /// Returns the fees credited to an operator for the requests it fulfilled
+    pub fn get_operator_earnings(&self, account_id: AccountId)-> OperatorEarnings{
+        return self.operator_earnings.get(&account_id).unwrap_or_default()
+    }
+
+    /// Returns the sum of the token deposits locked by open requests
+    pub fn get_token_escrowed_total(&self, token_id: AccountId)-> U128{
+        return self.token_escrowed.get(&token_id).unwrap_or(0).into()
+    }
+
+    /// Returns the collected fees of a token that the owner can withdraw
+    pub fn get_token_fees(&self, token_id: AccountId)-> U128{
+        return self.token_fees.get(&token_id).unwrap_or(0).into()
+    }
+
+    /// Returns the token refund of an account rejected by the token contract, see `claim_token_refund`
+    pub fn get_token_refund(&self, token_id: AccountId, account_id: AccountId)-> U128{
+        return self.token_refunds.get(&(token_id, account_id)).unwrap_or(0).into()
+    }
+
+    /// Returns the token deposits credited to an operator for the requests it fulfilled
+    pub fn get_operator_token_earnings(&self, account_id: AccountId, token_id: AccountId)-> U128{
+        return self.operator_token_earnings.get(&(account_id, token_id)).unwrap_or(0).into()
+    }
+
+    /// Returns true while new requests are rejected
+    pub fn is_paused(&self)-> bool{
+        return self.paused
+    }
+
+    /// Returns the layout version of the contract state
+    pub fn get_state_version(&self)-> u16{
+        return self.state_version
+    }
+
+    /// Returns the owner account
+    pub fn get_owner(&self)-> AccountId{
+        return self.owner_id.clone()
+    }
+
+    /// Returns the account proposed as the new owner, if any
+    pub fn get_proposed_owner(&self)-> Option<AccountId>{
+        return self.proposed_owner_id.clone()
+    }
+
+    /// Returns true if the account has the given role
+    pub fn has_role(&self, account_id: AccountId, role: Role)-> bool{
+        return (role == Role::Owner && account_id == self.owner_id) || self.roles.contains(&(account_id, role))
+    }
+
+    /// Returns the accounts that were granted the given role
+    pub fn get_role_members(&self, role: Role)-> Vec<AccountId>{
+        return self.roles.iter()
+            .filter(|(_, member_role)| *member_role == role)
+            .map(|(account_id, _)| account_id)
+            .collect()
+    }
+
+    /// Returns the NEAR fees accounting of the gateway
+    pub fn get_treasury_stats(&self)-> TreasuryStats{
+        return TreasuryStats {
+            collected: self.fees_collected.into(),
+            refunded: self.fees_refunded.into(),
+            withdrawn: self.fees_withdrawn.into(),
+            withdrawable: (self.fees_collected - self.fees_withdrawn).into(),
+            escrowed: self.escrowed.into(),
+            credits: self.credits_total.into(),
+            storage_balances: self.storage_balances_total.into()
+        }
+    }
+
+    /// Returns the amount of stored requests in any status
+    pub fn get_requests_count(&self)-> u64{
+        return self.requests.len()
+    }
+
+    /// Returns up to `limit` stored requests with the given status in arrival order, starting after
+    /// the given cursor (from the beginning if none). Use the returned `next_cursor` to request the
+    /// next page, cursors are request sequence numbers
+    pub fn get_requests_by_status(&self, status: RequestStatus, cursor: Option<U64>, limit: u64)-> RequestsPage{
+        let limit = std::cmp::min(limit, MAX_PAGE_LIMIT) as usize;
+        let from = match cursor {
+            Some(cursor) => Bound::Excluded((status as u8, cursor.0)),
+            None => Bound::Included((status as u8, 0))
+        };
+        let entries: Vec<((u8, u64), RequestKey)> = self.status_index
+            .range((from, Bound::Included((status as u8, u64::MAX))))
+            .take(limit)
+            .collect();
+        let next_cursor = match entries.last() {
+            Some((index_key, _)) if matches!(self.status_index.higher(index_key), Some((next_status, _)) if next_status == status as u8) => {
+                Some(U64::from(index_key.1))
+            },
+            _ => None
+        };
+        return RequestsPage {
+            requests: entries.iter()
+                .map(|(_, key)| self.requests.get(key).expect("Indexed request not found"))
+                .collect(),
+            next_cursor
+        }
+    }
+}
+
+#[near_bindgen]
+impl DiaApiGatewayContract {
+    /*************************/
+    /*  Maintenance methods  */
+    /*************************/
+
+    /// Deletes up to `limit` expired pending requests (oldest first) and refunds their deposits
+    /// to the originating contracts. Anyone can call it, the caller gets the purge bounty for
+    /// every deleted request, capped by the value of the storage released and taken from the
+    /// storage deposit of the request. Returns the amount of deleted requests
+    pub fn purge_expired(&mut self, limit: u64)-> u64{
+        let limit = std::cmp::min(limit, MAX_PAGE_LIMIT) as usize;
+        let expired: Vec<RequestKey> = self.queue.iter()
+            .take(MAX_PURGE_SCAN)
+            .map(|(_, key)| key)
+            .filter(|key| {
+                let request = self.requests.get(key).expect("Queued request not found");
+                /* Deliveries in flight are settled by their callback, unless it never ran */
+                request.is_expired() && (request.status != RequestStatus::Delivering || request.is_delivery_stuck())
+            })
+            .take(limit)
+            .collect();
+        /* Group the refunds (deposit, storage deposit) by originating contract and payment */
+        let mut refunds: Vec<(AccountId, Payment, u128, u128)> = Vec::new();
+        let mut bounty = 0;
+        for key in expired.iter() {
+            let initial_storage = env::storage_usage();
+            let request: Request = self.requests.remove(key).expect("Request not found");
+            self.queue.remove(&request.seq.0);
+            self.status_index.remove(&(request.status as u8, request.seq.0));
+            self.internal_remove_failed_delivery(key);
+            GatewayEvent::RequestDeleted { contract_id: key.0.clone(), request_id: request.request_id }.emit();
+            let released_storage = initial_storage.saturating_sub(env::storage_usage());
+            let request_bounty = std::cmp::min(self.purge_bounty, released_storage as u128 * STORAGE_PRICE_PER_BYTE);
+            let storage_refund = request.storage_deposit.0.saturating_sub(request_bounty);
+            bounty += request_bounty;
+            self.internal_release_deposit(&request);
+            match refunds.iter_mut().find(|(account_id, payment, _, _)| {
+                *account_id == request.contract_account_id && *payment == request.payment
+            }) {
+                Some((_, _, amount, storage)) => {
+                    *amount += request.deposit.0;
+                    *storage += storage_refund;
+                },
+                None => refunds.push((request.contract_account_id, request.payment, request.deposit.0, storage_refund))
+            }
+        }
+        for (account_id, payment, amount, storage) in refunds {
+            self.internal_refund(account_id.clone(), &payment, amount);
+            self.internal_refund_storage(account_id, &payment, storage);
+        }
+        if bounty > 0 {
+            Promise::new(env::predecessor_account_id()).transfer(bounty);
+        }
+        return expired.len() as u64
+    }
+}
+
+#[near_bindgen]
+impl DiaApiGatewayContract {
+    /*********************************/
+    /*  Storage management (NEP-145)  */
+    /*********************************/
+
+    /// Registers an account (the caller by default) or adds the attached deposit to its storage
+    /// balance. With `registration_only` the deposit above the min balance is refunded
+    #[payable]
+    pub fn storage_deposit(&mut self, account_id: Option<AccountId>, registration_only: Option<bool>)-> StorageBalance{
+        let account_id = account_id.unwrap_or_else(env::predecessor_account_id);
+        assert!(
+            env::is_valid_account_id(account_id.as_bytes()),
+            "The account ID is invalid"
+        );
+        let attached_deposit = env::attached_deposit();
+        let registration_only = registration_only.unwrap_or(false);
+        let (balance, deposit) = match self.storage_balances.get(&account_id) {
+            Some(balance) => (balance, if registration_only { 0 } else { attached_deposit }),
+            None => {
+                let min_balance = self.storage_balance_bounds().min.0;
+                assert!(attached_deposit >= min_balance,
+                    "The min storage balance is {}, but the given attached deposit is {}",
+                    min_balance,
+                    attached_deposit
+                );
+                (0, if registration_only { min_balance } else { attached_deposit })
+            }
+        };
+        self.storage_balances.insert(&account_id, &(balance + deposit));
+        self.storage_balances_total += deposit;
+        GatewayEvent::StorageBalanceUpdate { account_id: account_id.clone(), balance: Some(U128::from(balance + deposit)) }.emit();
+        if attached_deposit > deposit {
+            Promise::new(env::predecessor_account_id()).transfer(attached_deposit - deposit);
+        }
+        return self.storage_balance_of(account_id).unwrap()
+    }
+
+    /// Withdraws `amount` (all by default) of the caller's available storage balance
+    #[payable]
+    pub fn storage_withdraw(&mut self, amount: Option<U128>)-> StorageBalance{
+        self.assert_one_yocto();
+        let account_id = env::predecessor_account_id();
+        let storage_balance = self.storage_balance_of(account_id.clone()).expect("The account is not registered");
+        let amount = amount.unwrap_or(storage_balance.available).0;
+        assert!(amount <= storage_balance.available.0,
+            "The available storage balance is {}, can not withdraw {}",
+            storage_balance.available.0,
+            amount
+        );
+        if amount > 0 {
+            self.storage_balances.insert(&account_id, &(storage_balance.total.0 - amount));
+            self.storage_balances_total -= amount;
+            GatewayEvent::StorageBalanceUpdate { account_id: account_id.clone(), balance: Some(U128::from(storage_balance.total.0 - amount)) }.emit();
+            Promise::new(account_id.clone()).transfer(amount);
+        }
+        return self.storage_balance_of(account_id).unwrap()
+    }
+
+    /// Unregisters the caller and transfers its whole storage balance back. An account with prepaid
+    /// credit has to use `force`, the credit is then transferred too. Its requests keep their storage
+    /// deposits, returned the way they were paid when deleted. Returns false if it was not registered
+    #[payable]
+    pub fn storage_unregister(&mut self, force: Option<bool>)-> bool{
+        self.assert_one_yocto();
+        let account_id = env::predecessor_account_id();
+        let balance = match self.storage_balances.get(&account_id) {
+            Some(balance) => balance,
+            None => return false
+        };
+        let credit = self.credits.get(&account_id).unwrap_or(0);
+        assert!(credit == 0 || force.unwrap_or(false),
+            "The account has a prepaid credit of {}, withdraw it or unregister with force",
+            credit
+        );
+        self.storage_balances.remove(&account_id);
+        self.storage_balances_total -= balance;
+        GatewayEvent::StorageBalanceUpdate { account_id: account_id.clone(), balance: None }.emit();
+        if credit > 0 {
+            self.credits.remove(&account_id);
+            self.credits_total -= credit;
+        }
+        Promise::new(account_id).transfer(balance + credit);
+        return true
+    }
+
+    /// Returns the storage balance of a registered account
+    pub fn storage_balance_of(&self, account_id: AccountId)-> Option<StorageBalance>{
+        let min_balance = self.storage_balance_bounds().min.0;
+        return self.storage_balances.get(&account_id).map(|balance| StorageBalance {
+            total: U128::from(balance),
+            available: U128::from(balance - min_balance)
+        })
+    }
+
+    /// Returns the min storage balance, the storage of the account registration
+    pub fn storage_balance_bounds(&self)-> StorageBalanceBounds{
+        return StorageBalanceBounds {
+            min: U128::from(ACCOUNT_STORAGE_BYTES * STORAGE_PRICE_PER_BYTE),
+            max: None
+        }
+    }
+}
+
+impl DiaApiGatewayContract {
+    /// Empty gateway state with the default configuration
+    fn internal_new(owner_id: AccountId) -> Self {
+        let mut contract = Self {
+            state_version: STATE_VERSION,
+            owner_id: owner_id.clone(),
+            requests: UnorderedMap::new(b"r".to_vec()),
+            delivery_attempts: LookupMap::new(b"v".to_vec()),
+            failed_deliveries: LookupMap::new(b"l".to_vec()),
+            max_delivery_retries: DEFAULT_MAX_DELIVERY_RETRIES,
+            queue: TreeMap::new(b"q".to_vec()),
+            status_index: TreeMap::new(b"i".to_vec()),
+            last_seq: 0,
+            default_ttl: DEFAULT_REQUEST_TTL,
+            ttls: LookupMap::new(b"t".to_vec()),
+            purge_bounty: DEFAULT_PURGE_BOUNTY,
+            escrowed: 0,
+            operator_earnings: LookupMap::new(b"e".to_vec()),
+            default_fee: Fee::Yocto(U128::from(DEPOSIT_FOR_REQUEST)),
+            data_keys: UnorderedMap::new(b"d".to_vec()),
+            near_usd_price: None,
+            price_slippage_bps: DEFAULT_PRICE_SLIPPAGE_BPS,
+            max_price_age: DEFAULT_MAX_PRICE_AGE,
+            fee_tokens: UnorderedMap::new(b"k".to_vec()),
+            token_escrowed: LookupMap::new(b"x".to_vec()),
+            token_fees: LookupMap::new(b"g".to_vec()),
+            token_refunds: LookupMap::new(b"u".to_vec()),
+            operator_token_earnings: LookupMap::new(b"o".to_vec()),
+            credits: LookupMap::new(b"c".to_vec()),
+            credits_total: 0,
+            fees_collected: 0,
+            fees_refunded: 0,
+            fees_withdrawn: 0,
+            roles: UnorderedSet::new(b"a".to_vec()),
+            proposed_owner_id: None,
+            paused: false,
+            symbols: LookupSet::new(b"s".to_vec()),
+            symbol_counts: LookupMap::new(b"n".to_vec()),
+            input_limits: InputLimits::default(),
+            storage_balances: LookupMap::new(b"b".to_vec()),
+            storage_balances_total: 0
+        };
+        /* The owner starts as the adapter operator, the role can be moved to other keys */
+        contract.roles.insert(&(owner_id, Role::Operator));
+        contract.internal_add_default_data_keys();
+        return contract;
+    }
+
+    /// Registers the endpoints served by dia-adapter, enabled and with the default fee
+    fn internal_add_default_data_keys(&mut self) {
+        for (data_key, description, data_item_format) in DEFAULT_DATA_KEYS.iter() {
+            self.data_keys.insert(&data_key.to_string(), &DataKeyInfo {
+                data_key: data_key.to_string(),
+                description: description.to_string(),
+                data_item_format: data_item_format.to_string(),
+                fee: None,
+                enabled: true,
+                schema_version: DEFAULT_SCHEMA_VERSION
+            });
+        }
+    }
+
+    /// Returns a registered data_key, panics if it is unknown
+    fn internal_get_data_key(&self, data_key: &str) -> DataKeyInfo {
+        return self.data_keys.get(&data_key.to_string())
+            .unwrap_or_else(|| env::panic(format!("Unknown data_key {}", data_key).as_bytes()))
+    }
+
+    /// Stamps a new request with the next sequence number and its deadline and stores it at the end of the pending queue.
+    /// Returns the storage (bytes) used by the request
+    fn internal_add_request(&mut self, mut request: Request) -> u64 {
+        let initial_storage = env::storage_usage();
+        self.last_seq += 1;
+        request.seq = U64::from(self.last_seq);
+        request.created_at = U64::from(env::block_timestamp());
+        request.expires_at = U64::from(env::block_timestamp().saturating_add(self.get_ttl(request.data_key.clone()).0));
+        let key = (request.contract_account_id.clone(), request.request_id.0);
+        assert!(
+            self.requests.insert(&key, &request).is_none(),
+            "Request {} already exists for {}",
+            request.request_id.0,
+            request.contract_account_id
+        );
+        self.queue.insert(&self.last_seq, &key);
+        self.status_index.insert(&(request.status as u8, self.last_seq), &key);
+        let storage_used = env::storage_usage() - initial_storage;
+        match &request.payment {
+            Payment::Token(token_id) => {
+                let escrowed = self.token_escrowed.get(token_id).unwrap_or(0);
+                self.token_escrowed.insert(token_id, &(escrowed + request.deposit.0));
+            },
+            _ => self.escrowed += request.deposit.0
+        }
+        return storage_used;
+    }
+
+    /// Records the cost of the storage used by a new request as its storage deposit, returned
+    /// when the record is deleted. Returns the cost (yocto-near)
+    fn internal_charge_storage(&mut self, key: &RequestKey, storage_used: u64) -> u128 {
+        let mut request = self.requests.get(key).expect("Request not found");
+        let storage_cost = storage_used as u128 * STORAGE_PRICE_PER_BYTE;
+        request.storage_deposit = U128::from(storage_cost);
+        self.requests.insert(key, &request);
+        return storage_cost;
+    }
+
+    /// Draws an amount from the prepaid credit of an account
+    fn internal_draw_credit(&mut self, account_id: &AccountId, amount: u128) {
+        let credit = self.credits.get(account_id).unwrap_or(0);
+        assert!(credit >= amount,
+            "The required fee is {}, but the credit balance is {}",
+            amount,
+            credit
+        );
+        self.credits.insert(account_id, &(credit - amount));
+        self.credits_total -= amount;
+    }
+
+    /// Draws the storage cost of a request from the storage balance of a registered account.
+    /// Returns false if the account is not registered
+    fn internal_draw_storage_balance(&mut self, account_id: &AccountId, amount: u128) -> bool {
+        let balance = match self.storage_balances.get(account_id) {
+            Some(balance) => balance,
+            None => return false
+        };
+        let available = balance - self.storage_balance_bounds().min.0;
+        assert!(available >= amount,
+            "The request storage costs {}, but the available storage balance is {}",
+            amount,
+            available
+        );
+        self.storage_balances.insert(account_id, &(balance - amount));
+        self.storage_balances_total -= amount;
+        return true;
+    }
+
+    /// Returns the storage deposit of a deleted request to the storage balance of registered
+    /// accounts, otherwise the way it was paid: NEAR transfer for attached deposits and prepaid
+    /// credit for the rest
+    fn internal_refund_storage(&mut self, account_id: AccountId, payment: &Payment, amount: u128) {
+        if amount == 0 {
+            return;
+        }
+        if let Some(balance) = self.storage_balances.get(&account_id) {
+            self.storage_balances.insert(&account_id, &(balance + amount));
+            self.storage_balances_total += amount;
+            return;
+        }
+        match payment {
+            Payment::Deposit => {
+                Promise::new(account_id).transfer(amount);
+            },
+            _ => {
+                let credit = self.credits.get(&account_id).unwrap_or(0);
+                self.credits.insert(&account_id, &(credit + amount));
+                self.credits_total += amount;
+            }
+        }
+    }
+
+    /// Removes the deposit of a closed request from the escrow totals
+    fn internal_release_deposit(&mut self, request: &Request) {
+        match &request.payment {
+            Payment::Token(token_id) => {
+                let escrowed = self.token_escrowed.get(token_id).unwrap_or(0);
+                self.token_escrowed.insert(token_id, &(escrowed - request.deposit.0));
+            },
+            _ => self.escrowed -= request.deposit.0
+        }
+    }
+
+    /// Moves a request to `Delivering` and calls its callback with the response, `on_fulfill`
+    /// settles the request with the outcome of the call
+    fn internal_deliver(&mut self, key: RequestKey, err: String, data: Value) -> Promise {
+        assert!(
+            env::prepaid_gas() - env::used_gas() > GAS_FOR_DELIVERY + GAS_FOR_ON_FULFILL,
+            "Not enough gas to deliver the response, it needs more than {} gas",
+            GAS_FOR_DELIVERY + GAS_FOR_ON_FULFILL
+        );
+        let operator_id = env::predecessor_account_id();
+        let request = self.internal_update_status(&key, RequestStatus::Delivering, &operator_id);
+        let (contract_id, request_id) = (key.0, request.request_id);
+        return Promise::new(contract_id.clone())
+            .function_call(
+                request.callback.into_bytes(),
+                near_sdk::serde_json::to_vec(&CallbackArgs {
+                    request_id,
+                    err,
+                    data
+                }).unwrap(),
+                0,
+                GAS_FOR_DELIVERY
+            )
+            .then(Promise::new(env::current_account_id()).function_call(
+                b"on_fulfill".to_vec(),
+                near_sdk::serde_json::to_vec(&OnFulfillArgs {
+                    contract_id,
+                    request_id,
+                    operator_id
+                }).unwrap(),
+                0,
+                GAS_FOR_ON_FULFILL
+            ));
+    }
+
+    /// Drops the delivery attempts and the failed delivery record of a delivered or deleted request
+    fn internal_remove_failed_delivery(&mut self, key: &RequestKey) {
+        self.delivery_attempts.remove(key);
+        if let Some(mut failures) = self.failed_deliveries.get(&key.0) {
+            let count = failures.len();
+            failures.retain(|failure| failure.request_id.0 != key.1);
+            if failures.is_empty() {
+                self.failed_deliveries.remove(&key.0);
+            }
+            else if failures.len() != count {
+                self.failed_deliveries.insert(&key.0, &failures);
+            }
+        }
+    }
+
+    /// Moves a request to a new status on behalf of `account_id`, closed requests leave the pending
+    /// queue and release their escrowed deposit: fulfilled requests credit it to the operator
+    /// `account_id`, any other final status refunds it to the originating contract
+    fn internal_update_status(&mut self, key: &RequestKey, status: RequestStatus, account_id: &AccountId) -> Request {
+        let mut request = self.requests.get(key).expect("Request not found");
+        assert!(
+            request.status.can_transition_to(status),
+            "Request can not go from {:?} to {:?}",
+            request.status,
+            status
+        );
+        /* Expired requests can only be closed as expired, failed or cancelled, deliveries started
+        before the expiration are settled by their outcome */
+        assert!(
+            !(request.is_expired()
+                && request.status != RequestStatus::Delivering
+                && (status == RequestStatus::Claimed || status == RequestStatus::Fulfilled || status == RequestStatus::Delivering)),
+            "Request expired at {}",
+            request.expires_at.0
+        );
+        /* The recorded lifecycle must match the chain time */
+        assert!(status != RequestStatus::Expired || request.is_expired(), "Request has not expired yet");
+        /* Deliveries in flight are settled by `on_fulfill`, unless it never ran */
+        assert!(
+            !(request.status == RequestStatus::Delivering && status == RequestStatus::Expired && !request.is_delivery_stuck()),
+            "The delivery can be settled until {}",
+            request.expires_at.0.saturating_add(DELIVERY_GRACE_PERIOD)
+        );
+        self.status_index.remove(&(request.status as u8, request.seq.0));
+        self.status_index.insert(&(status as u8, request.seq.0), key);
+        request.status = status;
+        if !status.is_open() {
+            self.queue.remove(&request.seq.0);
+            self.internal_release_deposit(&request);
+            if status == RequestStatus::Fulfilled {
+                let operator_id = account_id.clone();
+                let mut earnings = self.operator_earnings.get(&operator_id).unwrap_or_default();
+                earnings.served_requests = U64::from(earnings.served_requests.0 + 1);
+                match &request.payment {
+                    Payment::Token(token_id) => {
+                        let token_key = (operator_id.clone(), token_id.clone());
+                        let earned = self.operator_token_earnings.get(&token_key).unwrap_or(0);
+                        self.operator_token_earnings.insert(&token_key, &(earned + request.deposit.0));
+                        let collected = self.token_fees.get(token_id).unwrap_or(0);
+                        self.token_fees.insert(token_id, &(collected + request.deposit.0));
+                    },
+                    _ => {
+                        earnings.earned = U128::from(earnings.earned.0 + request.deposit.0);
+                        self.fees_collected += request.deposit.0;
+                    }
+                }
+                self.operator_earnings.insert(&operator_id, &earnings);
+            }
+            else {
+                self.internal_refund(request.contract_account_id.clone(), &request.payment, request.deposit.0);
+            }
+        }
+        self.requests.insert(key, &request);
+        GatewayEvent::RequestStatus {
+            contract_id: key.0.clone(),
+            request_id: request.request_id,
+            status,
+            account_id: account_id.clone()
+        }.emit();
+        return request;
+    }
+
+    /// Sends a deposit back the way it was paid: NEAR transfer, prepaid credit or NEP-141 `ft_transfer`
+    fn internal_refund(&mut self, account_id: AccountId, payment: &Payment, amount: u128) {
+        if amount == 0 {
+            return;
+        }
+        match payment {
+            Payment::Deposit => {
+                self.fees_refunded += amount;
+                Promise::new(account_id).transfer(amount);
+            },
+            Payment::Credit => {
+                self.fees_refunded += amount;
+                let credit = self.credits.get(&account_id).unwrap_or(0);
+                self.credits.insert(&account_id, &(credit + amount));
+                self.credits_total += amount;
+            },
+            Payment::Token(token_id) => {
+                self.internal_ft_transfer(token_id.clone(), account_id, amount, false);
+            }
+        }
+    }
+
+    /// Transfers tokens with `ft_transfer`, `on_token_transfer` records the transfer if the token rejects it
+    fn internal_ft_transfer(&mut self, token_id: AccountId, receiver_id: AccountId, amount: u128, withdrawal: bool) -> Promise {
+        return Promise::new(token_id.clone())
+            .function_call(
+                b"ft_transfer".to_vec(),
+                near_sdk::serde_json::to_vec(&FtTransferArgs {
+                    receiver_id: receiver_id.clone(),
+                    amount: U128::from(amount),
+                    memo: None
+                }).unwrap(),
+                1,
+                GAS_FOR_FT_TRANSFER
+            )
+            .then(Promise::new(env::current_account_id()).function_call(
+                b"on_token_transfer".to_vec(),
+                near_sdk::serde_json::to_vec(&OnTokenTransferArgs {
+                    token_id,
+                    receiver_id,
+                    amount: U128::from(amount),
+                    withdrawal
+                }).unwrap(),
+                0,
+                GAS_FOR_ON_TOKEN_TRANSFER
+            ));
+    }
+
+    /// Converts a fee to yocto-near, usd fees need a NEAR/USD price not older than `max_price_age`
+    fn internal_fee_to_yocto(&self, fee: &Fee) -> u128 {
+        return match fee {
+            Fee::Yocto(amount) => amount.0,
+            Fee::UsdCents(usd_cents) => {
+                let near_usd_price = self.near_usd_price.as_ref().expect("There is no NEAR/USD price to charge usd fees");
+                let price_age = env::block_timestamp().saturating_sub(near_usd_price.updated_at.0);
+                assert!(
+                    price_age <= self.max_price_age,
+                    "The NEAR/USD price is stale, it was updated {} ns ago",
+                    price_age
+                );
+                /* usd_cents / 100 / (price / USD_PRICE_DECIMALS) NEAR, plus the slippage buffer */
+                let yocto = (usd_cents.0 as u128)
+                    .checked_mul(USD_PRICE_DECIMALS / 100 * ONE_NEAR)
+                    .expect("Fee overflow")
+                    / near_usd_price.price.0;
+                yocto
+                    .checked_mul(10_000 + self.price_slippage_bps as u128)
+                    .expect("Fee overflow")
+                    / 10_000
+            }
+        }
+    }
+
+    fn assert_one_yocto(&self) {
+        assert_eq!(env::attached_deposit(), 1, "Requires attached deposit of exactly 1 yoctoNEAR");
+    }
+
+    fn assert_input_limits(&self, data_key: &str, data_item: &str, callback: &str) {
+        for (name, value, max_len) in [
+            ("data_key", data_key, self.input_limits.max_data_key_len),
+            ("data_item", data_item, self.input_limits.max_data_item_len),
+            ("callback", callback, self.input_limits.max_callback_len)
+        ].iter() {
+            assert!(
+                value.len() <= *max_len as usize,
+                "The {} is {} bytes long, the max length is {}",
+                name,
+                value.len(),
+                max_len
+            );
+        }
+    }
+
+    fn assert_data_key_enabled(&self, data_key: &str) {
+        assert!(self.internal_get_data_key(data_key).enabled, "The data_key {} is disabled", data_key);
+    }
+
+    fn assert_data_item_valid(&self, data_key: &str, data_item: &str) {
+        assert!(
+            self.is_valid_data_item(data_key.to_string(), data_item.to_string()),
+            "Unknown data_item {} for {}",
+            data_item,
+            data_key
+        );
+    }
+
+    fn assert_not_paused(&self) {
+        assert!(!self.paused, "The gateway is paused, new requests are not accepted");
+    }
+
+    fn assert_pauser(&self) {
+        let account_id = env::predecessor_account_id();
+        assert!(
+            self.has_role(account_id.clone(), Role::Pauser) || self.has_role(account_id, Role::Owner),
+            "Can only be called by an account with the Pauser or Owner role"
+        );
+    }
+
+    fn assert_owner_id(&self) {
+        assert_eq!(
+            env::predecessor_account_id(),
+            self.owner_id,
+            "Can only be called by the owner"
+        );
+    }
+
+    fn assert_role(&self, role: Role) {
+        assert!(
+            self.has_role(env::predecessor_account_id(), role),
+            "Can only be called by an account with the {:?} role",
+            role
+        );
+    }
+}
+
+/**************/
+/* Unit tests */
+/**************/
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use near_sdk::MockedBlockchain;
+    use near_sdk::{testing_env, VMContext};
+
+
+    static OWNER: &str = "testowner.testnet";
+    static TEST_ACCOUNT: &str = "client.testnet";
+    const REQUEST_DEPOSIT: u128 = 2*DEPOSIT_FOR_REQUEST; // default fee plus enough for the storage of a request
+
+    /// Borsh encoded V0 state (`LegacyDiaApiGatewayContract`) owned by OWNER with one pending
+    /// request of TEST_ACCOUNT: id 1231223, "quotation", "BTC", "callback"
+    static V0_STATE_FIXTURE: &[u8] = &[
+            17, 0, 0, 0, 116, 101, 115, 116, 111, 119, 110, 101, 114, 46, 116, 101,
+            115, 116, 110, 101, 116, 1, 0, 0, 0, 14, 0, 0, 0, 99, 108, 105,
+            101, 110, 116, 46, 116, 101, 115, 116, 110, 101, 116, 119, 201, 18, 0, 0,
+            0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 9, 0, 0, 0, 113,
+            117, 111, 116, 97, 116, 105, 111, 110, 3, 0, 0, 0, 66, 84, 67, 8,
+            0, 0, 0, 99, 97, 108, 108, 98, 97, 99, 107,
+    ];
+
+    /// Borsh encoded V0 state owned by OWNER where TEST_ACCOUNT sent request 1231223 twice:
+    /// "quotation", "BTC", "callback" then "quotation", "ETH", "callback"
+    static V0_DUPLICATE_STATE_FIXTURE: &[u8] = &[
+            17, 0, 0, 0, 116, 101, 115, 116, 111, 119, 110, 101, 114, 46, 116, 101,
+            115, 116, 110, 101, 116, 2, 0, 0, 0, 14, 0, 0, 0, 99, 108, 105,
+            101, 110, 116, 46, 116, 101, 115, 116, 110, 101, 116, 119, 201, 18, 0, 0,
+            0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 9, 0, 0, 0, 113,
+            117, 111, 116, 97, 116, 105, 111, 110, 3, 0, 0, 0, 66, 84, 67, 8,
+            0, 0, 0, 99, 97, 108, 108, 98, 97, 99, 107, 14, 0, 0, 0, 99,
+            108, 105, 101, 110, 116, 46, 116, 101, 115, 116, 110, 101, 116, 119, 201, 18,
+            0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 9, 0, 0,
+            0, 113, 117, 111, 116, 97, 116, 105, 111, 110, 3, 0, 0, 0, 69, 84,
+            72, 8, 0, 0, 0, 99, 97, 108, 108, 98, 97, 99, 107,
+    ];
+
+    /// Set the contract context
+    pub fn initialize(){
+        let context = get_context(String::from(TEST_ACCOUNT), 10);                    
+        testing_env!(context); 
+    }
+    
+    /// Creates a contract
+    pub fn create_contract() -> DiaApiGatewayContract{
+        let contract = super::DiaApiGatewayContract::new(String::from(OWNER));
+        return contract;
+    }
+
+    ///Creates a request as a client and returns the expected saved value
+    pub fn create_request(contract: &mut DiaApiGatewayContract) -> Request{
+        let charged = contract.request(U128::from(1231223), String::from("quotation"), String::from("BTC"), String::from("callback"));
+        assert!(charged.0 > DEPOSIT_FOR_REQUEST, "The storage of the request was not charged");
+        let expected_request = Request{
+            contract_account_id: String::from(TEST_ACCOUNT),
+            request_id: U128::from(1231223),
+            data_key: String::from("quotation"),
+            data_item: String::from("BTC"),
+            callback: String::from("callback"),
+            seq: U64::from(1),
+            status: RequestStatus::Pending,
+            deposit: U128::from(DEPOSIT_FOR_REQUEST),
+            created_at: U64::from(0),
+            expires_at: U64::from(DEFAULT_REQUEST_TTL),
+            payment: Payment::Deposit,
+            storage_deposit: U128::from(charged.0 - DEPOSIT_FOR_REQUEST)
+        };
+        return expected_request;
+    }
+
+    /// Creates a request as a registered client, only the fee is charged
+    pub fn create_request_registered(contract: &mut DiaApiGatewayContract) -> Request{
+        let charged = contract.request(U128::from(1231223), String::from("quotation"), String::from("BTC"), String::from("callback"));
+        assert_eq!(charged, U128::from(DEPOSIT_FOR_REQUEST), "Only the fee should be charged");
+        return contract.get_request(String::from(TEST_ACCOUNT), U128::from(1231223)).unwrap();
+    }
+
+    /// Defines the context for the contract
+    fn get_context(predecessor_account_id: String, storage_usage: u64) -> VMContext {
+        VMContext {
+            current_account_id: OWNER.to_string(),
+            signer_account_id: OWNER.to_string(),
+            signer_account_pk: vec![0, 1, 2],
+            predecessor_account_id,
+            input: vec![],
+            block_index: 0,
+            block_timestamp: 0,
+            account_balance: ONE_NEAR,
+            account_locked_balance: 0,
+            storage_usage,
+            attached_deposit: REQUEST_DEPOSIT,
+            prepaid_gas: 10u64.pow(18),
+            random_seed: vec![0, 1, 2],
+            is_view: false,
+            output_data_receivers: vec![],
+            epoch_height: 19,
+        }
+    }
+
+    /// Sets the context of a callback receiving `result`, keeping the contract storage
+    fn set_promise_result(context: VMContext, result: PromiseResult) {
+        let storage = env::take_blockchain_interface().unwrap().as_mut_mocked_blockchain().unwrap().take_storage();
+        env::set_blockchain_interface(Box::new(MockedBlockchain::new(
+            context,
+            Default::default(),
+            Default::default(),
+            vec![result],
+            storage,
+            Default::default()
+        )));
+    }
+
+    #[test]
+    fn test_creation() {
+        initialize();
+        let contract = create_contract();
+        assert_eq!(contract.owner_id, String::from(OWNER), "Owner is different from the expected");
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_default_creation() {
+        initialize();
+        <DiaApiGatewayContract as Default>::default();
+    }
+
+    #[test]
+    fn test_client_methods(){
+        initialize();
+        let mut contract = create_contract();
+        let expected_request = create_request(&mut contract);
+        
+        if let Some(request) = contract.requests.get(&(String::from(TEST_ACCOUNT), 1231223)) {
+            assert_eq!(expected_request, request, "Saved request has wrong field values");
+        }
+        else{
+            panic!("Request not saved");
+        }
+    }
+
+    #[test]
+    fn test_adapter_methods(){
+        initialize();
+        /* Create a request as a client */
+        let mut contract = create_contract();
+        let expected_request = create_request(&mut contract);
+        /* Change context to match a method called by the adapter */
+        let context = get_context(String::from(OWNER), env::storage_usage());                    
+        testing_env!(context); 
+      
+        println!("Testing 'get_pending_requests_count' method");
+        let pending_requests_count = contract.get_pending_requests_count();
+        assert_eq!(pending_requests_count, 1 as u64, "Wrong value ({}) in pending requests", pending_requests_count);
+
+        println!("Testing 'get_pending_requests' method");
+        let pending_requests = contract.get_pending_requests(0, 10);
+        if let Some(request) = pending_requests.get(0) {
+            assert_eq!(expected_request, *request, "Method 'get_pending_requests' returns wrong data");
+        }
+        else{
+            panic!("Method 'get_pending_requests' dont returns data");
+        }
+
+        println!("Testing 'remove' method");
+        contract.remove(String::from(TEST_ACCOUNT), U128::from(1231223));
+        assert_eq!(contract.get_pending_requests_count(), 0, "Request was not removed");
+        let request = contract.get_request(String::from(TEST_ACCOUNT), U128::from(1231223)).unwrap();
+        assert_eq!(request.status, RequestStatus::Fulfilled, "Removed request should be fulfilled");
+    }
+
+    #[test]
+    fn test_request_overpayment(){
+        let mut context = get_context(String::from(TEST_ACCOUNT), 10);
+        context.attached_deposit = 3*DEPOSIT_FOR_REQUEST;
+        testing_env!(context);
+        let mut contract = create_contract();
+        let charged = contract.request(U128::from(1), String::from("quotation"), String::from("BTC"), String::from("callback"));
+        let request = contract.get_request(String::from(TEST_ACCOUNT), U128::from(1)).unwrap();
+        assert_eq!(charged.0, DEPOSIT_FOR_REQUEST + request.storage_deposit.0, "Wrong amount charged");
+        assert_eq!(request.deposit, U128::from(DEPOSIT_FOR_REQUEST), "Only the fee should be kept");
+        assert_eq!(contract.get_escrowed_total(), U128::from(DEPOSIT_FOR_REQUEST), "Only the fee should be escrowed");
+    }
+
+    #[test]
+    fn test_fee_schedule(){
+        initialize();
+        let mut contract = create_contract();
+        let context = get_context(String::from(OWNER), env::storage_usage());
+        testing_env!(context);
+        contract.set_default_fee(U128::from(2*ONE_NEAR_CENT));
+        contract.set_fee(String::from("symbols"), Some(U128::from(ONE_NEAR_CENT/2)));
+        assert_eq!(contract.get_fee(String::from("quotation")), U128::from(2*ONE_NEAR_CENT), "Wrong default fee");
+        assert_eq!(contract.get_fee(String::from("symbols")), U128::from(ONE_NEAR_CENT/2), "Wrong data_key fee");
+
+        let context = get_context(String::from(TEST_ACCOUNT), env::storage_usage());
+        testing_env!(context);
+        contract.request(U128::from(1), String::from("symbols"), String::from(""), String::from("callback"));
+        let request = contract.get_request(String::from(TEST_ACCOUNT), U128::from(1)).unwrap();
+        assert_eq!(request.deposit, U128::from(ONE_NEAR_CENT/2), "Wrong fee charged");
+
+        let context = get_context(String::from(OWNER), env::storage_usage());
+        testing_env!(context);
+        contract.set_fee(String::from("symbols"), None);
+        assert_eq!(contract.get_fee(String::from("symbols")), U128::from(2*ONE_NEAR_CENT), "Fee override was not removed");
+    }
+
+    #[test]
+    fn test_data_key_registry(){
+        initialize();
+        let mut contract = create_contract();
+        assert_eq!(contract.get_supported_data_keys().len(), DEFAULT_DATA_KEYS.len(), "Default data_keys were not registered");
+        let context = get_context(String::from(OWNER), env::storage_usage());
+        testing_env!(context);
+        contract.set_data_key(String::from("volume"), String::from("Volume"), String::from("Exchange"), 2);
+        contract.set_fee(String::from("volume"), Some(U128::from(ONE_NEAR_CENT/2)));
+        contract.set_data_key(String::from("volume"), String::from("Trade volume"), String::from("Exchange"), 3);
+        assert_eq!(contract.get_data_key(String::from("volume")), Some(DataKeyInfo {
+            data_key: String::from("volume"),
+            description: String::from("Trade volume"),
+            data_item_format: String::from("Exchange"),
+            fee: Some(Fee::Yocto(U128::from(ONE_NEAR_CENT/2))),
+            enabled: true,
+            schema_version: 3
+        }), "Updating a data_key must keep its fee");
+
+        contract.set_data_key_enabled(String::from("symbols"), false);
+        contract.remove_data_key(String::from("supply"));
+        let supported: Vec<String> = contract.get_supported_data_keys().into_iter().map(|info| info.data_key).collect();
+        assert!(!supported.contains(&String::from("symbols")), "Disabled data_keys are not supported");
+        assert!(!supported.contains(&String::from("supply")), "Removed data_keys are not supported");
+        assert!(contract.get_data_key(String::from("symbols")).is_some(), "Disabled data_keys stay registered");
+
+        let context = get_context(String::from(TEST_ACCOUNT), env::storage_usage());
+        testing_env!(context);
+        create_request(&mut contract);
+    }
+
+    #[test]
+    #[should_panic(expected = "Unknown data_key quotaton")]
+    fn test_request_unknown_data_key(){
+        initialize();
+        let mut contract = create_contract();
+        contract.request(U128::from(1), String::from("quotaton"), String::from("BTC"), String::from("callback"));
+    }
+
+    #[test]
+    #[should_panic(expected = "The data_key quotation is disabled")]
+    fn test_request_disabled_data_key(){
+        initialize();
+        let mut contract = create_contract();
+        let context = get_context(String::from(OWNER), env::storage_usage());
+        testing_env!(context);
+        contract.set_data_key_enabled(String::from("quotation"), false);
+
+        let context = get_context(String::from(TEST_ACCOUNT), env::storage_usage());
+        testing_env!(context);
+        create_request(&mut contract);
+    }
+
+    #[test]
+    #[should_panic(expected = "Unknown data_key prices")]
+    fn test_set_fee_unknown_data_key(){
+        initialize();
+        let mut contract = create_contract();
+        let context = get_context(String::from(OWNER), env::storage_usage());
+        testing_env!(context);
+        contract.set_fee(String::from("prices"), Some(U128::from(ONE_NEAR_CENT)));
+    }
+
+    #[test]
+    #[should_panic(expected = "Can only be called by an account with the Owner role")]
+    fn test_set_data_key_not_owner(){
+        initialize();
+        let mut contract = create_contract();
+        contract.set_data_key(String::from("prices"), String::from(""), String::from(""), 1);
+    }
+
+    #[test]
+    fn test_symbols(){
+        initialize();
+        let mut contract = create_contract();
+        assert!(contract.is_valid_data_item(String::from("quotation"), String::from("BTCC")), "Data_keys without symbols accept any data_item");
+        let context = get_context(String::from(OWNER), env::storage_usage());
+        testing_env!(context);
+        let symbols = vec![String::from("BTC"), String::from("ETH"), String::from("BTC")];
+        assert_eq!(contract.add_symbols(String::from("quotation"), symbols), 2, "Duplicated symbols must be counted once");
+        assert!(contract.is_valid_data_item(String::from("quotation"), String::from("ETH")), "Symbol was not added");
+        assert!(!contract.is_valid_data_item(String::from("quotation"), String::from("BTCC")), "Unknown symbols are not valid");
+        assert!(contract.is_valid_data_item(String::from("supply"), String::from("BTCC")), "Symbols are kept per data_key");
+
+        let context = get_context(String::from(TEST_ACCOUNT), env::storage_usage());
+        testing_env!(context);
+        create_request(&mut contract);
+
+        let context = get_context(String::from(OWNER), env::storage_usage());
+        testing_env!(context);
+        assert_eq!(contract.remove_symbols(String::from("quotation"), vec![String::from("ETH"), String::from("XRP")]), 1, "Wrong symbols count");
+        assert_eq!(contract.remove_symbols(String::from("quotation"), vec![String::from("BTC")]), 0, "Wrong symbols count");
+        assert!(contract.is_valid_data_item(String::from("quotation"), String::from("BTCC")), "Data_keys without symbols accept any data_item");
+    }
+
+    #[test]
+    #[should_panic(expected = "Unknown data_item BTCC for quotation")]
+    fn test_request_unknown_symbol(){
+        initialize();
+        let mut contract = create_contract();
+        let context = get_context(String::from(OWNER), env::storage_usage());
+        testing_env!(context);
+        contract.add_symbols(String::from("quotation"), vec![String::from("BTC")]);
+
+        let context = get_context(String::from(TEST_ACCOUNT), env::storage_usage());
+        testing_env!(context);
+        contract.request(U128::from(1), String::from("quotation"), String::from("BTCC"), String::from("callback"));
+    }
+
+    #[test]
+    #[should_panic(expected = "Can only be called by an account with the Operator role")]
+    fn test_add_symbols_not_operator(){
+        initialize();
+        let mut contract = create_contract();
+        contract.add_symbols(String::from("quotation"), vec![String::from("BTC")]);
+    }
+
+    #[test]
+    fn test_usd_fees(){
+        initialize();
+        let mut contract = create_contract();
+        let context = get_context(String::from(OWNER), env::storage_usage());
+        testing_env!(context);
+        contract.set_fee_usd(String::from("quotation"), U64::from(5));
+        contract.set_price_slippage_bps(100);
+        /* 1 NEAR = 5 USD */
+        contract.update_near_usd_price(U128::from(5*USD_PRICE_DECIMALS));
+        assert_eq!(contract.get_fee_config(String::from("quotation")), Fee::UsdCents(U64::from(5)), "Wrong fee config");
+        /* 5 cents = 0.01 NEAR, plus 1% */
+        assert_eq!(contract.get_fee(String::from("quotation")), U128::from(ONE_NEAR_CENT*101/100), "Wrong usd fee conversion");
+        assert_eq!(contract.get_fee(String::from("symbols")), U128::from(DEPOSIT_FOR_REQUEST), "Yocto fees should not be converted");
+    }
+
+    #[test]
+    #[should_panic(expected = "The NEAR/USD price is stale")]
+    fn test_usd_fees_with_stale_price(){
+        initialize();
+        let mut contract = create_contract();
+        let context = get_context(String::from(OWNER), env::storage_usage());
+        testing_env!(context);
+        contract.set_default_fee_usd(U64::from(5));
+        contract.update_near_usd_price(U128::from(5*USD_PRICE_DECIMALS));
+
+        let mut context = get_context(String::from(TEST_ACCOUNT), env::storage_usage());
+        context.block_timestamp = DEFAULT_MAX_PRICE_AGE + 1;
+        testing_env!(context);
+        create_request(&mut contract);
+    }
+
+    #[test]
+    fn test_prepaid_credit(){
+        let mut context = get_context(String::from(TEST_ACCOUNT), 10);
+        context.attached_deposit = 4*DEPOSIT_FOR_REQUEST;
+        testing_env!(context);
+        let mut contract = create_contract();
+        assert_eq!(contract.deposit_credit(), U128::from(4*DEPOSIT_FOR_REQUEST), "Wrong credit after deposit");
+
+        let mut context = get_context(String::from(TEST_ACCOUNT), env::storage_usage());
+        context.attached_deposit = 0;
+        context.account_balance = ONE_NEAR;
+        testing_env!(context);
+        let charged: u128 = (0..2)
+            .map(|id| contract.request(U128::from(id), String::from("quotation"), String::from("BTC"), String::from("callback")).0)
+            .sum();
+        let mut credit = 4*DEPOSIT_FOR_REQUEST - charged;
+        assert_eq!(contract.get_credit(String::from(TEST_ACCOUNT)), U128::from(credit), "Fees should be drawn from the credit");
+        let request = contract.get_request(String::from(TEST_ACCOUNT), U128::from(0)).unwrap();
+        assert_eq!(request.payment, Payment::Credit, "Wrong payment");
+
+        /* Refunds of credit-paid requests go back to the credit */
+        contract.cancel_request(U128::from(0));
+        credit += DEPOSIT_FOR_REQUEST;
+        assert_eq!(contract.get_credit(String::from(TEST_ACCOUNT)), U128::from(credit), "Refund should be credited");
+        contract.delete_request(U128::from(0));
+        credit += request.storage_deposit.0;
+        assert_eq!(contract.get_credit(String::from(TEST_ACCOUNT)), U128::from(credit), "Storage deposit should be credited");
+
+        contract.withdraw_credit(U128::from(credit));
+        assert_eq!(contract.get_credit(String::from(TEST_ACCOUNT)), U128::from(0), "Credit was not withdrawn");
+    }
+
+    #[test]
+    #[should_panic(expected = "Requires a deposit to add to the credit")]
+    fn test_deposit_credit_without_deposit(){
+        initialize();
+        let mut contract = create_contract();
+        let mut context = get_context(String::from(TEST_ACCOUNT), env::storage_usage());
+        context.attached_deposit = 0;
+        testing_env!(context);
+        contract.deposit_credit();
+    }
+
+    #[test]
+    #[should_panic(expected = "The required fee is")]
+    fn test_request_without_credit(){
+        let mut context = get_context(String::from(TEST_ACCOUNT), 10);
+        context.attached_deposit = 0;
+        testing_env!(context);
+        let mut contract = create_contract();
+        create_request(&mut contract);
+    }
+
+    #[test]
+    fn test_treasury(){
+        initialize();
+        let mut contract = create_contract();
+        for id in 0..3 {
+            contract.request(U128::from(id), String::from("quotation"), String::from("BTC"), String::from("callback"));
+        }
+        contract.cancel_request(U128::from(0));
+        let mut context = get_context(String::from(OWNER), env::storage_usage());
+        context.account_balance = ONE_NEAR;
+        testing_env!(context);
+        contract.remove(String::from(TEST_ACCOUNT), U128::from(1));
+        contract.withdraw_fees(String::from("treasury.testnet"), U128::from(DEPOSIT_FOR_REQUEST/2));
+
+        let expected_stats = TreasuryStats {
+            collected: U128::from(DEPOSIT_FOR_REQUEST),
+            refunded: U128::from(DEPOSIT_FOR_REQUEST),
+            withdrawn: U128::from(DEPOSIT_FOR_REQUEST/2),
+            withdrawable: U128::from(DEPOSIT_FOR_REQUEST/2),
+            escrowed: U128::from(DEPOSIT_FOR_REQUEST),
+            credits: U128::from(0),
+            storage_balances: U128::from(0)
+        };
+        assert_eq!(contract.get_treasury_stats(), expected_stats, "Wrong treasury stats");
+    }
+
+    #[test]
+    #[should_panic(expected = "The withdrawable fees are")]
+    fn test_withdraw_escrowed_deposits(){
+        initialize();
+        let mut contract = create_contract();
+        create_request(&mut contract);
+        let mut context = get_context(String::from(OWNER), env::storage_usage());
+        context.account_balance = ONE_NEAR;
+        testing_env!(context);
+        contract.withdraw_fees(String::from(OWNER), U128::from(DEPOSIT_FOR_REQUEST));
+    }
+
+    #[test]
+    fn test_storage_management(){
+        initialize();
+        let mut contract = create_contract();
+        let min_balance = contract.storage_balance_bounds().min.0;
+        assert_eq!(contract.storage_balance_of(String::from(TEST_ACCOUNT)), None, "Account should not be registered");
+        let storage_balance = contract.storage_deposit(None, None);
+        assert_eq!(storage_balance, StorageBalance {
+            total: U128::from(REQUEST_DEPOSIT),
+            available: U128::from(REQUEST_DEPOSIT - min_balance)
+        }, "Wrong storage balance after registration");
+
+        /* Registered accounts only attach the fee */
+        let mut context = get_context(String::from(TEST_ACCOUNT), env::storage_usage());
+        context.attached_deposit = DEPOSIT_FOR_REQUEST;
+        testing_env!(context);
+        let expected_request = create_request_registered(&mut contract);
+        let available = REQUEST_DEPOSIT - min_balance - expected_request.storage_deposit.0;
+        assert_eq!(contract.storage_balance_of(String::from(TEST_ACCOUNT)).unwrap().available, U128::from(available), "Storage should be drawn from the storage balance");
+        assert_eq!(contract.get_treasury_stats().storage_balances, U128::from(REQUEST_DEPOSIT - expected_request.storage_deposit.0), "Wrong storage balances total");
+
+        /* Deleted requests return the storage deposit to the storage balance */
+        contract.cancel_request(U128::from(1231223));
+        contract.delete_request(U128::from(1231223));
+        assert_eq!(contract.storage_balance_of(String::from(TEST_ACCOUNT)).unwrap().available, U128::from(REQUEST_DEPOSIT - min_balance), "Storage deposit should return to the storage balance");
+
+        let mut context = get_context(String::from(TEST_ACCOUNT), env::storage_usage());
+        context.attached_deposit = 1;
+        testing_env!(context);
+        let storage_balance = contract.storage_withdraw(Some(U128::from(DEPOSIT_FOR_REQUEST)));
+        assert_eq!(storage_balance.total, U128::from(REQUEST_DEPOSIT - DEPOSIT_FOR_REQUEST), "Wrong storage balance after withdrawal");
+        assert_eq!(contract.storage_withdraw(None).available, U128::from(0), "The whole available balance should be withdrawn");
+        assert!(contract.storage_unregister(None), "Account was not unregistered");
+        assert!(!contract.storage_unregister(None), "Account was unregistered twice");
+        assert_eq!(contract.get_treasury_stats().storage_balances, U128::from(0), "Wrong storage balances total");
+    }
+
+    #[test]
+    fn test_storage_deposit_registration_only(){
+        initialize();
+        let mut contract = create_contract();
+        let min_balance = contract.storage_balance_bounds().min.0;
+        let storage_balance = contract.storage_deposit(Some(String::from("other.testnet")), Some(true));
+        assert_eq!(storage_balance, StorageBalance {
+            total: U128::from(min_balance),
+            available: U128::from(0)
+        }, "Only the min balance should be kept");
+        assert_eq!(contract.storage_deposit(Some(String::from("other.testnet")), Some(true)).total, U128::from(min_balance), "Registered accounts should get the deposit back");
+        assert_eq!(contract.storage_deposit(Some(String::from("other.testnet")), None).total, U128::from(min_balance + REQUEST_DEPOSIT), "Deposit was not added");
+    }
+
+    #[test]
+    #[should_panic(expected = "The request storage costs")]
+    fn test_request_without_storage_balance(){
+        initialize();
+        let mut contract = create_contract();
+        contract.storage_deposit(None, Some(true));
+        create_request(&mut contract);
+    }
+
+    #[test]
+    #[should_panic(expected = "The min storage balance is")]
+    fn test_storage_deposit_below_min(){
+        let mut context = get_context(String::from(TEST_ACCOUNT), 10);
+        context.attached_deposit = 1;
+        testing_env!(context);
+        let mut contract = create_contract();
+        contract.storage_deposit(None, None);
+    }
+
+    #[test]
+    #[should_panic(expected = "Requires attached deposit of exactly 1 yoctoNEAR")]
+    fn test_storage_withdraw_without_yocto(){
+        initialize();
+        let mut contract = create_contract();
+        contract.storage_deposit(None, None);
+        contract.storage_withdraw(None);
+    }
+
+    #[test]
+    fn test_storage_unregister_with_credit(){
+        initialize();
+        let mut contract = create_contract();
+        contract.storage_deposit(None, None);
+        contract.deposit_credit();
+        let mut context = get_context(String::from(TEST_ACCOUNT), env::storage_usage());
+        context.attached_deposit = 1;
+        testing_env!(context);
+        assert!(contract.storage_unregister(Some(true)), "Account was not unregistered");
+        assert_eq!(contract.get_credit(String::from(TEST_ACCOUNT)), U128::from(0), "Credit should be transferred");
+        assert_eq!(contract.get_treasury_stats().credits, U128::from(0), "Wrong credits total");
+    }
+
+    #[test]
+    #[should_panic(expected = "withdraw it or unregister with force")]
+    fn test_storage_unregister_without_force(){
+        initialize();
+        let mut contract = create_contract();
+        contract.storage_deposit(None, None);
+        contract.deposit_credit();
+        let mut context = get_context(String::from(TEST_ACCOUNT), env::storage_usage());
+        context.attached_deposit = 1;
+        testing_env!(context);
+        contract.storage_unregister(None);
+    }
+
+    #[test]
+    fn test_roles(){
+        initialize();
+        let mut contract = create_contract();
+        create_request(&mut contract);
+        let context = get_context(String::from(OWNER), env::storage_usage());
+        testing_env!(context);
+        assert!(contract.has_role(String::from(OWNER), Role::Owner), "Owner should have the Owner role");
+        assert_eq!(contract.get_role_members(Role::Operator), vec![String::from(OWNER)], "Owner should start as operator");
+        contract.add_role(String::from("adapter.testnet"), Role::Operator);
+        contract.revoke_role(String::from(OWNER), Role::Operator);
+        assert_eq!(contract.get_role_members(Role::Operator), vec![String::from("adapter.testnet")], "Wrong operators");
+
+        let context = get_context(String::from("adapter.testnet"), env::storage_usage());
+        testing_env!(context);
+        contract.remove(String::from(TEST_ACCOUNT), U128::from(1231223));
+        assert_eq!(contract.get_operator_earnings(String::from("adapter.testnet")).served_requests, U64::from(1), "Operator should be credited");
+    }
+
+    #[test]
+    #[should_panic(expected = "Can only be called by an account with the Operator role")]
+    fn test_revoked_operator(){
+        initialize();
+        let mut contract = create_contract();
+        create_request(&mut contract);
+        let context = get_context(String::from(OWNER), env::storage_usage());
+        testing_env!(context);
+        contract.revoke_role(String::from(OWNER), Role::Operator);
+        contract.remove(String::from(TEST_ACCOUNT), U128::from(1231223));
+    }
+
+    #[test]
+    #[should_panic(expected = "Can only be called by an account with the Owner role")]
+    fn test_add_role_without_owner_role(){
+        initialize();
+        let mut contract = create_contract();
+        contract.add_role(String::from(TEST_ACCOUNT), Role::Operator);
+    }
+
+    #[test]
+    fn test_ownership_transfer(){
+        initialize();
+        let mut contract = create_contract();
+        let context = get_context(String::from(OWNER), env::storage_usage());
+        testing_env!(context);
+        contract.propose_owner(String::from("newowner.testnet"));
+        assert_eq!(contract.get_proposed_owner(), Some(String::from("newowner.testnet")), "Wrong proposed owner");
+        assert_eq!(contract.get_owner(), String::from(OWNER), "Owner should not change before acceptance");
+
+        let context = get_context(String::from("newowner.testnet"), env::storage_usage());
+        testing_env!(context);
+        contract.accept_ownership();
+        assert_eq!(contract.get_owner(), String::from("newowner.testnet"), "Ownership was not transferred");
+        assert_eq!(contract.get_proposed_owner(), None, "Proposal should be cleared");
+        assert!(!contract.has_role(String::from(OWNER), Role::Owner), "Previous owner should lose the Owner role");
+        assert!(!contract.has_role(String::from(OWNER), Role::Operator), "Previous owner should lose the Operator role");
+        assert!(contract.get_role_members(Role::Operator).is_empty(), "No operator should be left");
+    }
+
+    #[test]
+    #[should_panic(expected = "There is no pending ownership proposal")]
+    fn test_accept_cancelled_ownership(){
+        initialize();
+        let mut contract = create_contract();
+        let context = get_context(String::from(OWNER), env::storage_usage());
+        testing_env!(context);
+        contract.propose_owner(String::from("newowner.testnet"));
+        contract.cancel_owner_proposal();
+
+        let context = get_context(String::from("newowner.testnet"), env::storage_usage());
+        testing_env!(context);
+        contract.accept_ownership();
+    }
+
+    #[test]
+    #[should_panic(expected = "Can only be called by the proposed owner")]
+    fn test_accept_ownership_by_other_account(){
+        initialize();
+        let mut contract = create_contract();
+        let context = get_context(String::from(OWNER), env::storage_usage());
+        testing_env!(context);
+        contract.propose_owner(String::from("newowner.testnet"));
+        contract.accept_ownership();
+    }
+
+    #[test]
+    fn test_pause(){
+        initialize();
+        let mut contract = create_contract();
+        create_request(&mut contract);
+        let context = get_context(String::from(OWNER), env::storage_usage());
+        testing_env!(context);
+        contract.add_role(String::from("pauser.testnet"), Role::Pauser);
+
+        let context = get_context(String::from("pauser.testnet"), env::storage_usage());
+        testing_env!(context);
+        contract.pause();
+        assert!(contract.is_paused(), "Gateway should be paused");
+
+        /* Cancellations keep working while paused */
+        let context = get_context(String::from(TEST_ACCOUNT), env::storage_usage());
+        testing_env!(context);
+        contract.cancel_request(U128::from(1231223));
+
+        let context = get_context(String::from("pauser.testnet"), env::storage_usage());
+        testing_env!(context);
+        contract.unpause();
+        assert!(!contract.is_paused(), "Gateway should not be paused");
+    }
+
+    #[test]
+    #[should_panic(expected = "The gateway is paused, new requests are not accepted")]
+    fn test_request_while_paused(){
+        initialize();
+        let mut contract = create_contract();
+        let context = get_context(String::from(OWNER), env::storage_usage());
+        testing_env!(context);
+        contract.pause();
+        let context = get_context(String::from(TEST_ACCOUNT), env::storage_usage());
+        testing_env!(context);
+        create_request(&mut contract);
+    }
+
+    #[test]
+    #[should_panic(expected = "Can only be called by an account with the Pauser or Owner role")]
+    fn test_pause_without_role(){
+        initialize();
+        let mut contract = create_contract();
+        contract.pause();
+    }
+
+    #[test]
+    fn test_token_fees(){
+        initialize();
+        let mut contract = create_contract();
+        let context = get_context(String::from(OWNER), env::storage_usage());
+        testing_env!(context);
+        contract.set_fee_token(String::from("usdc.testnet"), U128::from(50));
+        assert_eq!(contract.get_fee_tokens(), vec![(String::from("usdc.testnet"), U128::from(50))], "Wrong fee tokens");
+
+        /* The storage of token-paid requests is drawn from the prepaid credit */
+        let context = get_context(String::from(TEST_ACCOUNT), env::storage_usage());
+        testing_env!(context);
+        contract.deposit_credit();
+
+        let context = get_context(String::from("usdc.testnet"), env::storage_usage());
+        testing_env!(context);
+        let msg = r#"{"request_id":"7","data_key":"quotation","data_item":"BTC","callback":"callback"}"#;
+        match contract.ft_on_transfer(String::from(TEST_ACCOUNT), U128::from(80), String::from(msg)) {
+            PromiseOrValue::Value(unused) => assert_eq!(unused, U128::from(30), "Wrong amount of unused tokens"),
+            PromiseOrValue::Promise(_) => panic!("Unused tokens should be returned as a value")
+        }
+        let request = contract.get_request(String::from(TEST_ACCOUNT), U128::from(7)).unwrap();
+        assert_eq!(request.payment, Payment::Token(String::from("usdc.testnet")), "Wrong fee token");
+        assert_eq!(request.deposit, U128::from(50), "Wrong token deposit");
+        assert_eq!(contract.get_credit(String::from(TEST_ACCOUNT)).0, REQUEST_DEPOSIT - request.storage_deposit.0, "Storage should be drawn from the credit");
+        assert_eq!(contract.get_token_escrowed_total(String::from("usdc.testnet")), U128::from(50), "Token deposit should be escrowed");
+        assert_eq!(contract.get_escrowed_total(), U128::from(0), "Token deposits should not be escrowed as NEAR");
+
+        let context = get_context(String::from(OWNER), env::storage_usage());
+        testing_env!(context);
+        contract.remove(String::from(TEST_ACCOUNT), U128::from(7));
+        assert_eq!(contract.get_token_escrowed_total(String::from("usdc.testnet")), U128::from(0), "Token deposit should leave escrow");
+        assert_eq!(contract.get_operator_token_earnings(String::from(OWNER), String::from("usdc.testnet")), U128::from(50), "Wrong operator token earnings");
+        assert_eq!(contract.get_token_fees(String::from("usdc.testnet")), U128::from(50), "Token fee should be collected");
+
+        contract.withdraw_token_fees(String::from("usdc.testnet"), String::from(OWNER), U128::from(20));
+        assert_eq!(contract.get_token_fees(String::from("usdc.testnet")), U128::from(30), "Token fees were not withdrawn");
+        /* The token rejects the transfer */
+        set_promise_result(get_context(String::from(OWNER), env::storage_usage()), PromiseResult::Failed);
+        assert!(!contract.on_token_transfer(String::from("usdc.testnet"), String::from(OWNER), U128::from(20), true), "Transfer should fail");
+        assert_eq!(contract.get_token_fees(String::from("usdc.testnet")), U128::from(50), "Rejected withdrawal should be collected again");
+    }
+
+    #[test]
+    #[should_panic(expected = "can not withdraw 51")]
+    fn test_withdraw_token_fees_above_collected(){
+        initialize();
+        let mut contract = create_contract();
+        let context = get_context(String::from(OWNER), env::storage_usage());
+        testing_env!(context);
+        contract.token_fees.insert(&String::from("usdc.testnet"), &50);
+        contract.withdraw_token_fees(String::from("usdc.testnet"), String::from(OWNER), U128::from(51));
+    }
+
+    #[test]
+    fn test_failed_token_refund(){
+        initialize();
+        let mut contract = create_contract();
+        let context = get_context(String::from(OWNER), env::storage_usage());
+        testing_env!(context);
+        contract.set_fee_token(String::from("usdc.testnet"), U128::from(50));
+        let context = get_context(String::from(TEST_ACCOUNT), env::storage_usage());
+        testing_env!(context);
+        contract.deposit_credit();
+        let context = get_context(String::from("usdc.testnet"), env::storage_usage());
+        testing_env!(context);
+        let msg = r#"{"request_id":"7","data_key":"quotation","data_item":"BTC","callback":"callback"}"#;
+        contract.ft_on_transfer(String::from(TEST_ACCOUNT), U128::from(50), String::from(msg));
+
+        let context = get_context(String::from(TEST_ACCOUNT), env::storage_usage());
+        testing_env!(context);
+        contract.cancel_request(U128::from(7));
+        /* The client is not registered with the token */
+        set_promise_result(get_context(String::from(OWNER), env::storage_usage()), PromiseResult::Failed);
+        assert!(!contract.on_token_transfer(String::from("usdc.testnet"), String::from(TEST_ACCOUNT), U128::from(50), false), "Transfer should fail");
+        assert_eq!(contract.get_token_refund(String::from("usdc.testnet"), String::from(TEST_ACCOUNT)), U128::from(50), "Rejected refund was not recorded");
+        assert_eq!(contract.get_token_fees(String::from("usdc.testnet")), U128::from(0), "Refunds are not fees");
+
+        let context = get_context(String::from(TEST_ACCOUNT), env::storage_usage());
+        testing_env!(context);
+        contract.claim_token_refund(String::from("usdc.testnet"));
+        assert_eq!(contract.get_token_refund(String::from("usdc.testnet"), String::from(TEST_ACCOUNT)), U128::from(0), "Claimed refund should be cleared");
+    }
+
+    #[test]
+    #[should_panic(expected = "There is no failed refund to claim")]
+    fn test_claim_missing_token_refund(){
+        initialize();
+        let mut contract = create_contract();
+        contract.claim_token_refund(String::from("usdc.testnet"));
+    }
+
+    #[test]
+    #[should_panic(expected = "The token is not accepted to pay fees")]
+    fn test_unknown_fee_token(){
+        initialize();
+        let mut contract = create_contract();
+        let context = get_context(String::from("fake.testnet"), env::storage_usage());
+        testing_env!(context);
+        let msg = r#"{"request_id":"7","data_key":"quotation","data_item":"BTC","callback":"callback"}"#;
+        contract.ft_on_transfer(String::from(TEST_ACCOUNT), U128::from(80), String::from(msg));
+    }
+
+    #[test]
+    #[should_panic(expected = "The required attached deposit is")]
+    fn test_request_with_low_deposit(){
+        initialize();
+        let mut contract = create_contract();
+        let context = get_context(String::from(OWNER), env::storage_usage());
+        testing_env!(context);
+        contract.set_fee(String::from("quotation"), Some(U128::from(2*DEPOSIT_FOR_REQUEST)));
+        let context = get_context(String::from(TEST_ACCOUNT), env::storage_usage());
+        testing_env!(context);
+        create_request(&mut contract);
+    }
+
+    #[test]
+    fn test_escrow(){
+        initialize();
+        let mut contract = create_contract();
+        for id in 0..3 {
+            contract.request(U128::from(id), String::from("quotation"), String::from("BTC"), String::from("callback"));
+        }
+        assert_eq!(contract.get_escrowed_total(), U128::from(3*DEPOSIT_FOR_REQUEST), "Deposits should be escrowed");
+        contract.cancel_request(U128::from(0));
+        assert_eq!(contract.get_escrowed_total(), U128::from(2*DEPOSIT_FOR_REQUEST), "Cancelled deposit should leave escrow");
+
+        let context = get_context(String::from(OWNER), env::storage_usage());
+        testing_env!(context);
+        contract.remove(String::from(TEST_ACCOUNT), U128::from(1));
+        contract.update_request_status(String::from(TEST_ACCOUNT), U128::from(2), RequestStatus::Failed);
+        assert_eq!(contract.get_escrowed_total(), U128::from(0), "Closed requests should leave escrow");
+        let expected_earnings = OperatorEarnings{
+            served_requests: U64::from(1),
+            earned: U128::from(DEPOSIT_FOR_REQUEST)
+        };
+        assert_eq!(contract.get_operator_earnings(String::from(OWNER)), expected_earnings, "Wrong operator earnings");
+        assert_eq!(contract.get_operator_earnings(String::from(TEST_ACCOUNT)), OperatorEarnings::default(), "Unexpected earnings");
+    }
+
+    #[test]
+    fn test_request_lifecycle(){
+        initialize();
+        let mut contract = create_contract();
+        for id in 0..3 {
+            contract.request(U128::from(id), String::from("quotation"), String::from("BTC"), String::from("callback"));
+        }
+        let context = get_context(String::from(OWNER), env::storage_usage());
+        testing_env!(context);
+
+        contract.update_request_status(String::from(TEST_ACCOUNT), U128::from(0), RequestStatus::Claimed);
+        assert_eq!(contract.get_pending_requests_count(), 3, "Claimed requests are still pending");
+        contract.update_request_status(String::from(TEST_ACCOUNT), U128::from(0), RequestStatus::Failed);
+        contract.update_request_status(String::from(TEST_ACCOUNT), U128::from(1), RequestStatus::Fulfilled);
+        assert_eq!(contract.get_pending_requests_count(), 1, "Closed requests should leave the queue");
+        assert_eq!(contract.get_requests_count(), 3, "Closed requests should be kept");
+
+        let ids = |page: RequestsPage| page.requests.iter().map(|request| request.request_id.0).collect::<Vec<u128>>();
+        assert_eq!(ids(contract.get_requests_by_status(RequestStatus::Failed, None, 10)), vec![0], "Wrong failed requests");
+        assert_eq!(ids(contract.get_requests_by_status(RequestStatus::Fulfilled, None, 10)), vec![1], "Wrong fulfilled requests");
+        assert_eq!(ids(contract.get_requests_by_status(RequestStatus::Pending, None, 10)), vec![2], "Wrong pending requests");
+        assert!(contract.get_requests_by_status(RequestStatus::Claimed, None, 10).requests.is_empty(), "Claimed request was closed");
+    }
+
+    #[test]
+    fn test_requests_by_status_paging(){
+        initialize();
+        let mut contract = create_contract();
+        for id in 0..5 {
+            contract.request(U128::from(id), String::from("quotation"), String::from("BTC"), String::from("callback"));
+        }
+        let context = get_context(String::from(OWNER), env::storage_usage());
+        testing_env!(context);
+
+        let ids = |page: &RequestsPage| page.requests.iter().map(|request| request.request_id.0).collect::<Vec<u128>>();
+        let page = contract.get_requests_by_status(RequestStatus::Pending, None, 2);
+        assert_eq!(ids(&page), vec![0, 1], "Wrong first page");
+        assert_eq!(page.next_cursor, Some(U64::from(2)), "Wrong cursor");
+
+        /* Requests leaving the status do not shift the next pages */
+        contract.update_request_status(String::from(TEST_ACCOUNT), U128::from(0), RequestStatus::Fulfilled);
+        let page = contract.get_requests_by_status(RequestStatus::Pending, page.next_cursor, 2);
+        assert_eq!(ids(&page), vec![2, 3], "Wrong second page");
+        let page = contract.get_requests_by_status(RequestStatus::Pending, page.next_cursor, 2);
+        assert_eq!(ids(&page), vec![4], "Wrong last page");
+        assert_eq!(page.next_cursor, None, "The last page has no cursor");
+
+        /* Deleted requests leave the index */
+        let context = get_context(String::from(TEST_ACCOUNT), env::storage_usage());
+        testing_env!(context);
+        contract.delete_request(U128::from(0));
+        assert!(contract.get_requests_by_status(RequestStatus::Fulfilled, None, 10).requests.is_empty(), "Deleted request still listed");
+    }
+
+    #[test]
+    fn test_fulfill(){
+        initialize();
+        let mut contract = create_contract();
+        create_request(&mut contract);
+        let context = get_context(String::from(OWNER), env::storage_usage());
+        testing_env!(context);
+        contract.fulfill(String::from(TEST_ACCOUNT), U128::from(1231223), String::new(), Value::from("BTC"));
+        let request = contract.get_request(String::from(TEST_ACCOUNT), U128::from(1231223)).unwrap();
+        assert_eq!(request.status, RequestStatus::Delivering, "Request should be delivering");
+        assert_eq!(contract.get_pending_requests_count(), 1, "Deliveries in flight stay in the queue");
+
+        /* The gateway settles the delivery */
+        set_promise_result(get_context(String::from(OWNER), env::storage_usage()), PromiseResult::Successful(vec![]));
+        assert!(contract.on_fulfill(String::from(TEST_ACCOUNT), U128::from(1231223), String::from(OWNER)), "Delivery should succeed");
+        let request = contract.get_request(String::from(TEST_ACCOUNT), U128::from(1231223)).unwrap();
+        assert_eq!(request.status, RequestStatus::Fulfilled, "Delivered request should be fulfilled");
+        assert_eq!(contract.get_pending_requests_count(), 0, "Fulfilled request should leave the queue");
+        let expected_earnings = OperatorEarnings{
+            served_requests: U64::from(1),
+            earned: U128::from(DEPOSIT_FOR_REQUEST)
+        };
+        assert_eq!(contract.get_operator_earnings(String::from(OWNER)), expected_earnings, "Wrong operator earnings");
+    }
+
+    #[test]
+    fn test_fulfill_failed_delivery(){
+        initialize();
+        let mut contract = create_contract();
+        create_request(&mut contract);
+        let context = get_context(String::from(OWNER), env::storage_usage());
+        testing_env!(context);
+        contract.fulfill(String::from(TEST_ACCOUNT), U128::from(1231223), String::new(), Value::from("BTC"));
+
+        contract.set_max_delivery_retries(0);
+        set_promise_result(get_context(String::from(OWNER), env::storage_usage()), PromiseResult::Failed);
+        assert!(!contract.on_fulfill(String::from(TEST_ACCOUNT), U128::from(1231223), String::from(OWNER)), "Delivery should fail");
+        let request = contract.get_request(String::from(TEST_ACCOUNT), U128::from(1231223)).unwrap();
+        assert_eq!(request.status, RequestStatus::Failed, "Undelivered request should fail");
+        assert_eq!(contract.get_escrowed_total(), U128::from(0), "Deposit should be refunded");
+        assert_eq!(contract.get_operator_earnings(String::from(OWNER)), OperatorEarnings::default(), "Unexpected earnings");
+    }
+
+    #[test]
+    #[should_panic(expected = "Not enough gas to deliver the response")]
+    fn test_fulfill_not_enough_gas(){
+        initialize();
+        let mut contract = create_contract();
+        create_request(&mut contract);
+        let mut context = get_context(String::from(OWNER), env::storage_usage());
+        /* Enough for the callback, not for refunding a token request if the callback fails */
+        context.prepaid_gas = GAS_FOR_DELIVERY + GAS_FOR_SETTLE_DELIVERY + 10_000_000_000_000;
+        testing_env!(context);
+        contract.fulfill(String::from(TEST_ACCOUNT), U128::from(1231223), String::new(), Value::from("BTC"));
+    }
+
+    #[test]
+    fn test_delivery_retries(){
+        initialize();
+        let mut contract = create_contract();
+        create_request(&mut contract);
+        let context = get_context(String::from(OWNER), env::storage_usage());
+        testing_env!(context);
+        assert_eq!(contract.get_max_delivery_retries(), DEFAULT_MAX_DELIVERY_RETRIES, "Wrong default retries");
+        contract.set_max_delivery_retries(1);
+        contract.fulfill(String::from(TEST_ACCOUNT), U128::from(1231223), String::new(), Value::from("BTC"));
+
+        /* First failure, the request can be delivered again */
+        let mut context = get_context(String::from(OWNER), env::storage_usage());
+        context.block_timestamp = 10;
+        set_promise_result(context, PromiseResult::Failed);
+        contract.on_fulfill(String::from(TEST_ACCOUNT), U128::from(1231223), String::from(OWNER));
+        let request = contract.get_request(String::from(TEST_ACCOUNT), U128::from(1231223)).unwrap();
+        assert_eq!(request.status, RequestStatus::Claimed, "Request with retries left should stay claimed");
+        let expected_failure = FailedDelivery {
+            request_id: U128::from(1231223),
+            callback: String::from("callback"),
+            attempts: 1,
+            failed_at: U64::from(10)
+        };
+        assert_eq!(contract.get_failed_deliveries(String::from(TEST_ACCOUNT)), vec![expected_failure.clone()], "Failure was not recorded");
+
+        /* The retry fails too, no retries left */
+        let context = get_context(String::from(OWNER), env::storage_usage());
+        testing_env!(context);
+        contract.fulfill(String::from(TEST_ACCOUNT), U128::from(1231223), String::new(), Value::from("BTC"));
+        set_promise_result(get_context(String::from(OWNER), env::storage_usage()), PromiseResult::Failed);
+        contract.on_fulfill(String::from(TEST_ACCOUNT), U128::from(1231223), String::from(OWNER));
+        let request = contract.get_request(String::from(TEST_ACCOUNT), U128::from(1231223)).unwrap();
+        assert_eq!(request.status, RequestStatus::Failed, "Request without retries left should fail");
+        assert_eq!(contract.get_escrowed_total(), U128::from(0), "Deposit should be refunded");
+        let failures = contract.get_failed_deliveries(String::from(TEST_ACCOUNT));
+        assert_eq!(failures.len(), 1, "Failures of a request should be merged");
+        assert_eq!(failures[0].attempts, 2, "Wrong attempts");
+
+        /* Deleting the request drops its failures */
+        let context = get_context(String::from(TEST_ACCOUNT), env::storage_usage());
+        testing_env!(context);
+        contract.delete_request(U128::from(1231223));
+        assert!(contract.get_failed_deliveries(String::from(TEST_ACCOUNT)).is_empty(), "Failures of deleted requests should be dropped");
+    }
+
+    #[test]
+    fn test_delivery_after_failure(){
+        initialize();
+        let mut contract = create_contract();
+        create_request(&mut contract);
+        let context = get_context(String::from(OWNER), env::storage_usage());
+        testing_env!(context);
+        contract.fulfill(String::from(TEST_ACCOUNT), U128::from(1231223), String::new(), Value::from("BTC"));
+        set_promise_result(get_context(String::from(OWNER), env::storage_usage()), PromiseResult::Failed);
+        contract.on_fulfill(String::from(TEST_ACCOUNT), U128::from(1231223), String::from(OWNER));
+
+        /* The adapter sends fresh data */
+        let context = get_context(String::from(OWNER), env::storage_usage());
+        testing_env!(context);
+        contract.fulfill(String::from(TEST_ACCOUNT), U128::from(1231223), String::new(), Value::from("ETH"));
+        set_promise_result(get_context(String::from(OWNER), env::storage_usage()), PromiseResult::Successful(vec![]));
+        assert!(contract.on_fulfill(String::from(TEST_ACCOUNT), U128::from(1231223), String::from(OWNER)), "Delivery should succeed");
+        let request = contract.get_request(String::from(TEST_ACCOUNT), U128::from(1231223)).unwrap();
+        assert_eq!(request.status, RequestStatus::Fulfilled, "Delivered request should be fulfilled");
+        assert!(contract.get_failed_deliveries(String::from(TEST_ACCOUNT)).is_empty(), "Failures of delivered requests should be dropped");
+    }
+
+    #[test]
+    fn test_failed_deliveries_limit(){
+        initialize();
+        let mut contract = create_contract();
+        for id in 0..(MAX_FAILED_DELIVERIES as u128 + 1) {
+            contract.request(U128::from(id), String::from("quotation"), String::from("BTC"), String::from("callback"));
+        }
+        for id in 0..(MAX_FAILED_DELIVERIES as u128 + 1) {
+            let context = get_context(String::from(OWNER), env::storage_usage());
+            testing_env!(context);
+            contract.fulfill(String::from(TEST_ACCOUNT), U128::from(id), String::new(), Value::from("BTC"));
+            set_promise_result(get_context(String::from(OWNER), env::storage_usage()), PromiseResult::Failed);
+            contract.on_fulfill(String::from(TEST_ACCOUNT), U128::from(id), String::from(OWNER));
+        }
+        let failures = contract.get_failed_deliveries(String::from(TEST_ACCOUNT));
+        assert_eq!(failures.len(), MAX_FAILED_DELIVERIES, "Failures should be capped");
+        assert_eq!(failures[0].request_id, U128::from(1), "Oldest failure should be dropped");
+
+        /* Dropping the record of the oldest request does not reset its attempts */
+        for attempt in 2..(DEFAULT_MAX_DELIVERY_RETRIES + 2) {
+            let context = get_context(String::from(OWNER), env::storage_usage());
+            testing_env!(context);
+            contract.fulfill(String::from(TEST_ACCOUNT), U128::from(0), String::new(), Value::from("BTC"));
+            set_promise_result(get_context(String::from(OWNER), env::storage_usage()), PromiseResult::Failed);
+            contract.on_fulfill(String::from(TEST_ACCOUNT), U128::from(0), String::from(OWNER));
+            let failures = contract.get_failed_deliveries(String::from(TEST_ACCOUNT));
+            assert_eq!(failures.last().map(|failure| failure.attempts), Some(attempt), "Wrong attempts");
+        }
+        let request = contract.get_request(String::from(TEST_ACCOUNT), U128::from(0)).unwrap();
+        assert_eq!(request.status, RequestStatus::Failed, "Request without retries left should fail");
+        assert!(contract.delivery_attempts.get(&(String::from(TEST_ACCOUNT), 0)).is_none(), "Attempts of failed requests should be dropped");
+    }
+
+    #[test]
+    fn test_stuck_delivery(){
+        initialize();
+        let mut contract = create_contract();
+        create_request(&mut contract);
+        let context = get_context(String::from(OWNER), env::storage_usage());
+        testing_env!(context);
+        contract.fulfill(String::from(TEST_ACCOUNT), U128::from(1231223), String::new(), Value::from("BTC"));
+
+        /* `on_fulfill` never ran, the request is expired but still in its grace period */
+        let mut context = get_context(String::from(TEST_ACCOUNT), env::storage_usage());
+        context.block_timestamp = DEFAULT_REQUEST_TTL;
+        testing_env!(context);
+        assert_eq!(contract.purge_expired(10), 0, "Deliveries in their grace period must not be purged");
+
+        let mut context = get_context(String::from(TEST_ACCOUNT), env::storage_usage());
+        context.block_timestamp = DEFAULT_REQUEST_TTL + DELIVERY_GRACE_PERIOD;
+        testing_env!(context);
+        contract.refund_expired_request(U128::from(1231223));
+        let request = contract.get_request(String::from(TEST_ACCOUNT), U128::from(1231223)).unwrap();
+        assert_eq!(request.status, RequestStatus::Expired, "Stuck delivery should be expired");
+        assert_eq!(contract.get_escrowed_total(), U128::from(0), "Deposit should leave escrow");
+
+        /* A late callback changes nothing */
+        let mut context = get_context(String::from(OWNER), env::storage_usage());
+        context.block_timestamp = DEFAULT_REQUEST_TTL + DELIVERY_GRACE_PERIOD;
+        set_promise_result(context, PromiseResult::Successful(vec![]));
+        contract.on_fulfill(String::from(TEST_ACCOUNT), U128::from(1231223), String::from(OWNER));
+        let request = contract.get_request(String::from(TEST_ACCOUNT), U128::from(1231223)).unwrap();
+        assert_eq!(request.status, RequestStatus::Expired, "Late callback should be ignored");
+    }
+
+    #[test]
+    fn test_purge_stuck_delivery(){
+        initialize();
+        let mut contract = create_contract();
+        create_request(&mut contract);
+        let context = get_context(String::from(OWNER), env::storage_usage());
+        testing_env!(context);
+        contract.fulfill(String::from(TEST_ACCOUNT), U128::from(1231223), String::new(), Value::from("BTC"));
+        let mut context = get_context(String::from(TEST_ACCOUNT), env::storage_usage());
+        context.block_timestamp = DEFAULT_REQUEST_TTL + DELIVERY_GRACE_PERIOD;
+        testing_env!(context);
+        assert_eq!(contract.purge_expired(10), 1, "Stuck delivery should be purged");
+        assert_eq!(contract.get_escrowed_total(), U128::from(0), "Deposit should leave escrow");
+    }
+
+    #[test]
+    #[should_panic(expected = "The delivery can be settled until")]
+    fn test_expire_delivery_in_flight(){
+        initialize();
+        let mut contract = create_contract();
+        create_request(&mut contract);
+        let context = get_context(String::from(OWNER), env::storage_usage());
+        testing_env!(context);
+        contract.fulfill(String::from(TEST_ACCOUNT), U128::from(1231223), String::new(), Value::from("BTC"));
+        let mut context = get_context(String::from(TEST_ACCOUNT), env::storage_usage());
+        context.block_timestamp = DEFAULT_REQUEST_TTL;
+        testing_env!(context);
+        contract.refund_expired_request(U128::from(1231223));
+    }
+
+    #[test]
+    #[should_panic(expected = "Deliveries are only managed by fulfill")]
+    fn test_update_delivering_request(){
+        initialize();
+        let mut contract = create_contract();
+        create_request(&mut contract);
+        let context = get_context(String::from(OWNER), env::storage_usage());
+        testing_env!(context);
+        contract.fulfill(String::from(TEST_ACCOUNT), U128::from(1231223), String::new(), Value::from("BTC"));
+        contract.remove(String::from(TEST_ACCOUNT), U128::from(1231223));
+    }
+
+    #[test]
+    #[should_panic(expected = "Can only be called by the gateway")]
+    fn test_on_fulfill_not_gateway(){
+        initialize();
+        let mut contract = create_contract();
+        create_request(&mut contract);
+        let context = get_context(String::from(OWNER), env::storage_usage());
+        testing_env!(context);
+        contract.fulfill(String::from(TEST_ACCOUNT), U128::from(1231223), String::new(), Value::from("BTC"));
+
+        set_promise_result(get_context(String::from(TEST_ACCOUNT), env::storage_usage()), PromiseResult::Successful(vec![]));
+        contract.on_fulfill(String::from(TEST_ACCOUNT), U128::from(1231223), String::from(TEST_ACCOUNT));
+    }
+
+    #[test]
+    #[should_panic(expected = "Can only be called by an account with the Operator role")]
+    fn test_fulfill_not_operator(){
+        initialize();
+        let mut contract = create_contract();
+        create_request(&mut contract);
+        contract.fulfill(String::from(TEST_ACCOUNT), U128::from(1231223), String::new(), Value::from("BTC"));
+    }
+
+    #[test]
+    fn test_cancel_request(){
+        initialize();
+        let mut contract = create_contract();
+        create_request(&mut contract);
+        contract.cancel_request(U128::from(1231223));
+        let request = contract.get_request(String::from(TEST_ACCOUNT), U128::from(1231223)).unwrap();
+        assert_eq!(request.status, RequestStatus::Cancelled, "Request was not cancelled");
+        assert_eq!(contract.get_pending_requests_count(), 0, "Cancelled request should leave the queue");
+    }
+
+    #[test]
+    #[should_panic(expected = "Request not found")]
+    fn test_cancel_request_of_other_contract(){
+        initialize();
+        let mut contract = create_contract();
+        create_request(&mut contract);
+        let context = get_context(String::from("other.testnet"), env::storage_usage());
+        testing_env!(context);
+        contract.cancel_request(U128::from(1231223));
+    }
+
+    #[test]
+    fn test_request_expiration(){
+        initialize();
+        let mut contract = create_contract();
+        let context = get_context(String::from(OWNER), env::storage_usage());
+        testing_env!(context);
+        contract.set_ttl(String::from("quotation"), Some(U64::from(ONE_HOUR_NS)));
+        assert_eq!(contract.get_ttl(String::from("quotation")), U64::from(ONE_HOUR_NS), "Wrong data_key TTL");
+        assert_eq!(contract.get_ttl(String::from("symbols")), U64::from(DEFAULT_REQUEST_TTL), "Wrong default TTL");
+
+        let context = get_context(String::from(TEST_ACCOUNT), env::storage_usage());
+        testing_env!(context);
+        let request = create_request(&mut contract);
+        assert_eq!(contract.get_request(String::from(TEST_ACCOUNT), request.request_id).unwrap().expires_at, U64::from(ONE_HOUR_NS), "Wrong deadline");
+
+        let mut context = get_context(String::from(TEST_ACCOUNT), env::storage_usage());
+        context.block_timestamp = ONE_HOUR_NS;
+        testing_env!(context);
+        contract.refund_expired_request(request.request_id);
+        let request = contract.get_request(String::from(TEST_ACCOUNT), request.request_id).unwrap();
+        assert_eq!(request.status, RequestStatus::Expired, "Request should be expired");
+        assert_eq!(contract.get_pending_requests_count(), 0, "Expired request should leave the queue");
+    }
+
+    #[test]
+    fn test_purge_expired(){
+        initialize();
+        let mut contract = create_contract();
+        for id in 0..3 {
+            contract.request(U128::from(id), String::from("quotation"), String::from("BTC"), String::from("callback"));
+        }
+        let mut context = get_context(String::from(OWNER), env::storage_usage());
+        context.block_timestamp = ONE_HOUR_NS;
+        testing_env!(context);
+        contract.set_ttl(String::from("quotation"), Some(U64::from(2*DEFAULT_REQUEST_TTL)));
+        /* Only requests made before the TTL change expire */
+        contract.request(U128::from(3), String::from("quotation"), String::from("BTC"), String::from("callback"));
+
+        let mut context = get_context(String::from("purger.testnet"), env::storage_usage());
+        context.block_timestamp = DEFAULT_REQUEST_TTL;
+        context.account_balance = ONE_NEAR;
+        testing_env!(context);
+        assert_eq!(contract.purge_expired(2), 2, "Wrong amount of purged requests");
+        assert_eq!(contract.get_requests_count(), 2, "Purged requests should be deleted");
+        assert_eq!(contract.purge_expired(10), 1, "Wrong amount of purged requests");
+        assert_eq!(contract.purge_expired(10), 0, "Requests not expired were purged");
+        let pending_requests = contract.get_pending_requests(0, 10);
+        assert_eq!(pending_requests.len(), 1, "Unexpired request should stay pending");
+        assert_eq!(pending_requests[0].request_id, U128::from(3), "Wrong request left pending");
+    }
+
+    #[test]
+    fn test_purge_storage_refund(){
+        initialize();
+        let mut contract = create_contract();
+        contract.deposit_credit();
+        let mut context = get_context(String::from(TEST_ACCOUNT), env::storage_usage());
+        context.attached_deposit = 0;
+        testing_env!(context);
+        let charged = contract.request(U128::from(1), String::from("quotation"), String::from("BTC"), String::from("callback"));
+        let credit = REQUEST_DEPOSIT - charged.0;
+
+        let mut context = get_context(String::from("purger.testnet"), env::storage_usage());
+        context.block_timestamp = DEFAULT_REQUEST_TTL;
+        testing_env!(context);
+        assert_eq!(contract.purge_expired(10), 1, "Wrong amount of purged requests");
+        /* The fee comes back whole, the bounty is taken from the storage deposit */
+        assert_eq!(
+            contract.get_credit(String::from(TEST_ACCOUNT)).0,
+            credit + charged.0 - DEFAULT_PURGE_BOUNTY,
+            "Wrong refund of a purged request"
+        );
+    }
+
+    #[test]
+    fn test_delete_request(){
+        initialize();
+        let mut contract = create_contract();
+        let expected_request = create_request(&mut contract);
+        contract.cancel_request(U128::from(1231223));
+        assert_eq!(contract.delete_request(U128::from(1231223)), expected_request.storage_deposit, "Wrong storage refund");
+        assert_eq!(contract.get_request(String::from(TEST_ACCOUNT), U128::from(1231223)), None, "Request was not deleted");
+        assert_eq!(contract.get_requests_count(), 0, "Request was not deleted");
+    }
+
+    #[test]
+    #[should_panic(expected = "Only closed requests can be deleted, request is Pending")]
+    fn test_delete_open_request(){
+        initialize();
+        let mut contract = create_contract();
+        create_request(&mut contract);
+        contract.delete_request(U128::from(1231223));
+    }
+
+    #[test]
+    #[should_panic(expected = "The required attached deposit is")]
+    fn test_request_without_storage_deposit(){
+        let mut context = get_context(String::from(TEST_ACCOUNT), 10);
+        context.attached_deposit = DEPOSIT_FOR_REQUEST;
+        testing_env!(context);
+        let mut contract = create_contract();
+        create_request(&mut contract);
+    }
+
+    #[test]
+    fn test_input_limits(){
+        initialize();
+        let mut contract = create_contract();
+        let context = get_context(String::from(OWNER), env::storage_usage());
+        testing_env!(context);
+        contract.set_input_limits(16, 3, 8);
+        assert_eq!(contract.get_input_limits(), InputLimits {
+            max_data_key_len: 16,
+            max_data_item_len: 3,
+            max_callback_len: 8
+        }, "Wrong input limits");
+
+        let context = get_context(String::from(TEST_ACCOUNT), env::storage_usage());
+        testing_env!(context);
+        create_request(&mut contract);
+    }
+
+    #[test]
+    #[should_panic(expected = "The data_item is 4 bytes long, the max length is 3")]
+    fn test_request_long_data_item(){
+        initialize();
+        let mut contract = create_contract();
+        let context = get_context(String::from(OWNER), env::storage_usage());
+        testing_env!(context);
+        contract.set_input_limits(16, 3, 8);
+
+        let context = get_context(String::from(TEST_ACCOUNT), env::storage_usage());
+        testing_env!(context);
+        contract.request(U128::from(1), String::from("quotation"), String::from("BTCC"), String::from("callback"));
+    }
+
+    #[test]
+    #[should_panic(expected = "The callback is 65 bytes long, the max length is 64")]
+    fn test_request_long_callback(){
+        initialize();
+        let mut contract = create_contract();
+        contract.request(U128::from(1), String::from("quotation"), String::from("BTC"), "c".repeat(65));
+    }
+
+    #[test]
+    #[should_panic(expected = "Request expired at")]
+    fn test_fulfill_expired_request(){
+        initialize();
+        let mut contract = create_contract();
+        create_request(&mut contract);
+        let mut context = get_context(String::from(OWNER), env::storage_usage());
+        context.block_timestamp = DEFAULT_REQUEST_TTL;
+        testing_env!(context);
+        contract.remove(String::from(TEST_ACCOUNT), U128::from(1231223));
+    }
+
+    #[test]
+    #[should_panic(expected = "Request has not expired yet")]
+    fn test_refund_request_before_expiration(){
+        initialize();
+        let mut contract = create_contract();
+        create_request(&mut contract);
+        contract.refund_expired_request(U128::from(1231223));
+    }
+
+    #[test]
+    #[should_panic(expected = "Request has not expired yet")]
+    fn test_expire_request_before_expiration(){
+        initialize();
+        let mut contract = create_contract();
+        create_request(&mut contract);
+        let context = get_context(String::from(OWNER), env::storage_usage());
+        testing_env!(context);
+        contract.update_request_status(String::from(TEST_ACCOUNT), U128::from(1231223), RequestStatus::Expired);
+    }
+
+    #[test]
+    #[should_panic(expected = "Request can not go from Fulfilled to Failed")]
+    fn test_closed_request_transition(){
+        initialize();
+        let mut contract = create_contract();
+        create_request(&mut contract);
+        let context = get_context(String::from(OWNER), env::storage_usage());
+        testing_env!(context);
+        contract.remove(String::from(TEST_ACCOUNT), U128::from(1231223));
+        contract.update_request_status(String::from(TEST_ACCOUNT), U128::from(1231223), RequestStatus::Failed);
+    }
+
+    #[test]
+    #[should_panic(expected = "already exists")]
+    fn test_duplicated_request(){
+        initialize();
+        let mut contract = create_contract();
+        create_request(&mut contract);
+        create_request(&mut contract);
+    }
+
+    #[test]
+    fn test_pending_requests_pagination(){
+        initialize();
+        let mut contract = create_contract();
+        for id in 0..5 {
+            contract.request(U128::from(id), String::from("quotation"), String::from("BTC"), String::from("callback"));
+        }
+        let context = get_context(String::from(OWNER), env::storage_usage());
+        testing_env!(context);
+        contract.remove(String::from(TEST_ACCOUNT), U128::from(1));
+
+        let ids = |requests: Vec<Request>| requests.iter().map(|request| request.request_id.0).collect::<Vec<u128>>();
+        assert_eq!(ids(contract.get_pending_requests(0, 2)), vec![0, 2], "Wrong first page");
+        assert_eq!(ids(contract.get_pending_requests(2, 2)), vec![3, 4], "Wrong second page");
+        assert_eq!(ids(contract.get_pending_requests(4, 2)), Vec::<u128>::new(), "Page after the end should be empty");
+
+        let page = contract.get_pending_requests_after(None, 3);
+        assert_eq!(ids(page.requests), vec![0, 2, 3], "Wrong first cursor page");
+        let page = contract.get_pending_requests_after(page.next_cursor, 3);
+        assert_eq!(ids(page.requests), vec![4], "Wrong last cursor page");
+        assert_eq!(page.next_cursor, None, "Last page should not return a cursor");
+    }
+
+    #[test]
+    fn test_requests_since(){
+        initialize();
+        let mut contract = create_contract();
+        for id in 0..4 {
+            contract.request(U128::from(id), String::from("quotation"), String::from("BTC"), String::from("callback"));
+        }
+        assert_eq!(contract.get_last_seq(), U64::from(4), "Wrong last sequence number");
+
+        let seqs = |requests: Vec<Request>| requests.iter().map(|request| request.seq.0).collect::<Vec<u64>>();
+        assert_eq!(seqs(contract.get_requests_since(U64::from(0), 2)), vec![1, 2], "Wrong requests since the beginning");
+        assert_eq!(seqs(contract.get_requests_since(U64::from(2), 10)), vec![3, 4], "Wrong requests since checkpoint");
+        assert_eq!(seqs(contract.get_requests_since(U64::from(4), 10)), Vec::<u64>::new(), "No request expected after the last one");
+    }
+
+    #[test]
+    fn test_migrate(){
+        initialize();
+        env::storage_write(b"STATE", V0_STATE_FIXTURE);
+        let expected_request = Request{
+            contract_account_id: String::from(TEST_ACCOUNT),
+            request_id: U128::from(1231223),
+            data_key: String::from("quotation"),
+            data_item: String::from("BTC"),
+            callback: String::from("callback"),
+            seq: U64::from(1),
+            status: RequestStatus::Pending,
+            deposit: U128::from(DEPOSIT_FOR_REQUEST),
+            created_at: U64::from(0),
+            expires_at: U64::from(DEFAULT_REQUEST_TTL),
+            payment: Payment::Deposit,
+            storage_deposit: U128::from(0)
+        };
+        let context = get_context(String::from(OWNER), 10);
+        testing_env!(context);
+
+        let contract = DiaApiGatewayContract::migrate();
+        assert_eq!(contract.owner_id, String::from(OWNER), "Owner is different from the expected");
+        assert_eq!(contract.get_state_version(), STATE_VERSION, "State version was not set");
+        assert_eq!(contract.get_pending_requests(0, 10), vec![expected_request], "Requests were not migrated");
+        assert_eq!(contract.get_escrowed_total(), U128::from(DEPOSIT_FOR_REQUEST), "Deposits were not escrowed");
+    }
+
+    #[test]
+    fn test_migrate_duplicate_requests(){
+        initialize();
+        env::storage_write(b"STATE", V0_DUPLICATE_STATE_FIXTURE);
+        let context = get_context(String::from(OWNER), 10);
+        testing_env!(context);
+
+        let contract = DiaApiGatewayContract::migrate();
+        let requests = contract.get_pending_requests(0, 10);
+        assert_eq!(requests.len(), 1, "Duplicate requests should be dropped");
+        assert_eq!(requests[0].data_item, String::from("BTC"), "The first request should be kept");
+        assert_eq!(contract.get_escrowed_total(), U128::from(DEPOSIT_FOR_REQUEST), "Only the kept request should be escrowed");
+        assert_eq!(contract.get_credit(String::from(TEST_ACCOUNT)), U128::from(DEPOSIT_FOR_REQUEST), "Deposit of the duplicate should be credited");
+    }
+
+    #[test]
+    fn test_legacy_state_fixture(){
+        initialize();
+        let legacy = LegacyDiaApiGatewayContract::try_from_slice(V0_STATE_FIXTURE).unwrap();
+        assert_eq!(legacy.owner_id, String::from(OWNER), "Owner is different from the expected");
+        assert_eq!(legacy.requests.len(), 1, "Wrong amount of legacy requests");
+        assert_eq!(legacy.requests[0].request_id, U128::from(1231223), "Wrong legacy request id");
+        assert_eq!(legacy.requests[0].callback, String::from("callback"), "Wrong legacy callback");
+        /* The legacy layout must never be mistaken for the current one */
+        assert!(DiaApiGatewayContract::try_from_slice(V0_STATE_FIXTURE).is_err(), "Legacy state read as current");
+    }
+
+    #[test]
+    fn test_migrate_current_state(){
+        initialize();
+        let mut contract = create_contract();
+        let expected_request = create_request(&mut contract);
+        let context = get_context(String::from(OWNER), env::storage_usage());
+        testing_env!(context);
+        contract.set_default_ttl(U64::from(ONE_HOUR_NS));
+        contract.set_max_delivery_retries(5);
+        env::state_write(&contract);
+
+        let context = get_context(String::from(OWNER), env::storage_usage());
+        testing_env!(context);
+        let migrated = DiaApiGatewayContract::migrate();
+        assert_eq!(migrated.get_state_version(), STATE_VERSION, "State version changed");
+        assert_eq!(migrated.get_ttl(String::from("quotation")), U64::from(ONE_HOUR_NS), "Configuration was lost");
+        assert_eq!(migrated.get_pending_requests(0, 10), vec![expected_request], "Requests were lost");
+        assert_eq!(migrated.get_last_seq(), U64::from(1), "Sequence number was lost");
+        assert_eq!(migrated.get_max_delivery_retries(), 5, "The delivery retries were lost");
+    }
+
+    #[test]
+    #[should_panic(expected = "Can only be called by the owner")]
+    fn test_migrate_not_owner(){
+        initialize();
+        env::storage_write(b"STATE", V0_STATE_FIXTURE);
+        DiaApiGatewayContract::migrate();
+    }
+
+    #[test]
+    fn test_stored_request(){
+        initialize();
+        let mut contract = create_contract();
+        let expected_request = create_request(&mut contract);
+        let stored = contract.requests.get(&(String::from(TEST_ACCOUNT), 1231223)).unwrap();
+        assert_eq!(stored, expected_request, "Stored request is different from the expected");
+    }
+
+    #[test]
+    #[should_panic(expected = "Can only be called by the owner")]
+    fn test_upgrade_not_owner(){
+        initialize();
+        let mut contract = create_contract();
+        contract.upgrade(Base64VecU8::from(vec![0, 1, 2]));
+    }
+
+    #[test]
+    fn test_event_logs(){
+        let event = GatewayEvent::FeeUpdate { data_key: Some(String::from("quotation")), fee: Some(Fee::UsdCents(U64::from(5))) };
+        assert_eq!(
+            event.to_log(),
+            r#"EVENT_JSON:{"standard":"dia-gateway","version":"1.0.0","event":"fee_update","data":{"data_key":"quotation","fee":{"UsdCents":"5"}}}"#,
+            "Unexpected fee_update log"
+        );
+        let event = GatewayEvent::RoleGranted { account_id: String::from(TEST_ACCOUNT), role: Role::Operator };
+        assert_eq!(
+            event.to_log(),
+            format!(r#"EVENT_JSON:{{"standard":"dia-gateway","version":"1.0.0","event":"role_granted","data":{{"account_id":"{}","role":"Operator"}}}}"#, TEST_ACCOUNT),
+            "Unexpected role_granted log"
+        );
+        let event = GatewayEvent::RequestDeleted { contract_id: String::from(TEST_ACCOUNT), request_id: U128::from(7) };
+        assert_eq!(
+            event.to_log(),
+            format!(r#"EVENT_JSON:{{"standard":"dia-gateway","version":"1.0.0","event":"request_deleted","data":{{"contract_id":"{}","request_id":"7"}}}}"#, TEST_ACCOUNT),
+            "Unexpected request_deleted log"
+        );
+        let event = GatewayEvent::InputLimitsUpdate(InputLimits::default());
+        assert_eq!(
+            event.to_log(),
+            r#"EVENT_JSON:{"standard":"dia-gateway","version":"1.0.0","event":"input_limits_update","data":{"max_data_key_len":32,"max_data_item_len":64,"max_callback_len":64}}"#,
+            "Unexpected input_limits_update log"
+        );
+    }
+
 }
 
 
@@ -11,7 +11,7 @@ static ALLOC: near_sdk::wee_alloc::WeeAlloc = near_sdk::wee_alloc::WeeAlloc::INI
 
 const ONE_NEAR:u128 = 1_000_000_000_000_000_000_000_000;
 const ONE_NEAR_CENT:u128 = ONE_NEAR/100;
-const DEPOSIT_FOR_REQUEST: u128 = ONE_NEAR_CENT; // amount that clients have to attach to make a request to the api
+const DEPOSIT_FOR_REQUEST: u128 = 2*ONE_NEAR_CENT; // amount attached to a request to the api: the fee plus the storage of the request, the excess is refunded
 const GAS_FOR_REQUEST: Gas = 50_000_000_000_000;
 const DIA_GATEWAY_ACCOUNT_ID: &str = "contract.dia.oracles.testnet";
 const SIGNER_DIA_ORACLES_ACCOUNT_ID:&str  = "dia.oracles.testnet";
@@ -132,8 +132,8 @@ impl SymbolsTestContract {
     /***********************/
     ///Callback to receive dia-api data
     pub fn callback(&mut self, request_id:U128, err: String, data: ResponseData){
-        //verify data origin
-        assert!(env::signer_account_id() == SIGNER_DIA_ORACLES_ACCOUNT_ID);
+        //verify data origin: relayed by the gateway (fulfill) or sent by the adapter
+        assert!(env::predecessor_account_id() == DIA_GATEWAY_ACCOUNT_ID || env::signer_account_id() == SIGNER_DIA_ORACLES_ACCOUNT_ID);
         //check for errrors in the request
         assert!(err.len()==0,err);
         //use symbols